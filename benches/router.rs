@@ -0,0 +1,121 @@
+//! Compares route lookup in HttpRouter against the linear regex scan it replaced, at 500 routes.
+//!     Run with `cargo bench --bench router`.
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use m_server::{
+    http::request::{HttpRequest, HttpRequestMethod},
+    router::HttpRouter,
+};
+use regex::Regex;
+
+const RESOURCES: usize = 100;
+const LOOKUPS: usize = 200_000;
+
+/// Five routes per resource, 500 in total
+fn route_paths() -> Vec<String> {
+    (0..RESOURCES)
+        .flat_map(|resource| {
+            [
+                format!("/api/v1/resource{}", resource),
+                format!("/api/v1/resource{}/search", resource),
+                format!("/api/v1/resource{}/{{id}}", resource),
+                format!("/api/v1/resource{}/{{id}}/items", resource),
+                format!("/api/v1/resource{}/{{id}}/items/{{item_id}}", resource),
+            ]
+        })
+        .collect()
+}
+
+/// Requests spread over every route, so the scan finds half of them past its midpoint
+fn request_paths() -> Vec<String> {
+    (0..RESOURCES)
+        .flat_map(|resource| {
+            [
+                format!("/api/v1/resource{}", resource),
+                format!("/api/v1/resource{}/search", resource),
+                format!("/api/v1/resource{}/42", resource),
+                format!("/api/v1/resource{}/42/items", resource),
+                format!("/api/v1/resource{}/42/items/7", resource),
+            ]
+        })
+        .collect()
+}
+
+/// Routing as it was done before the route tree, each route's regex tried in the order added
+struct LinearRouter {
+    routes: HashMap<HttpRequestMethod, Vec<Regex>>,
+}
+
+impl LinearRouter {
+    fn new(paths: &[String]) -> LinearRouter {
+        let regexes = paths.iter()
+            .map(|path| Regex::new(&Self::convert_path_to_regex(path)).unwrap())
+            .collect();
+        LinearRouter {
+            routes: HashMap::from([(HttpRequestMethod::Get, regexes)]),
+        }
+    }
+
+    fn find_route(&self, method: &HttpRequestMethod, path: &str) -> Option<usize> {
+        self.routes.get(method)?
+            .iter()
+            .position(|regex| regex.captures(path).is_some())
+    }
+
+    fn convert_path_to_regex(path: &str) -> String {
+        let mut regex_pattern = "^".to_string();
+        for segment in path.split('/') {
+            if !segment.is_empty() {
+                regex_pattern.push('/');
+            }
+            if segment.starts_with('{') && segment.ends_with('}') {
+                let param_name = &segment[1..segment.len() - 1];
+                regex_pattern.push_str(&format!("(?P<{}>[^/]+)", param_name));
+            } else {
+                regex_pattern.push_str(segment);
+            }
+        }
+        regex_pattern.push('$');
+        regex_pattern
+    }
+}
+
+fn time_lookups<F>(requests: &[String], mut lookup: F) -> Duration
+where
+    F: FnMut(&str) -> bool,
+{
+    let start = Instant::now();
+    for path in requests.iter().cycle().take(LOOKUPS) {
+        assert!(lookup(black_box(path)), "No route for {}", path);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let routes = route_paths();
+    let requests = request_paths();
+    let method = HttpRequestMethod::Get;
+
+    let mut router = HttpRouter::new();
+    for path in &routes {
+        router.add_route(HttpRequestMethod::Get, path, |_: &mut HttpRequest| ());
+    }
+    let linear = LinearRouter::new(&routes);
+
+    // warm up both before timing
+    time_lookups(&requests, |path| router.matched_route(&method, path).is_some());
+    time_lookups(&requests, |path| linear.find_route(&method, path).is_some());
+
+    let tree = time_lookups(&requests, |path| router.matched_route(&method, path).is_some());
+    let scan = time_lookups(&requests, |path| linear.find_route(&method, path).is_some());
+
+    let per_lookup = |elapsed: Duration| elapsed.as_nanos() as f64 / LOOKUPS as f64;
+    println!("{} routes, {} lookups", routes.len(), LOOKUPS);
+    println!("route tree:  {:>10.1} ns/lookup", per_lookup(tree));
+    println!("linear scan: {:>10.1} ns/lookup", per_lookup(scan));
+    println!("speedup:     {:>10.1}x", per_lookup(scan) / per_lookup(tree));
+}
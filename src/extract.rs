@@ -0,0 +1,365 @@
+use std::{net::IpAddr, sync::Arc};
+
+use crate::{
+    handler::State,
+    http::{
+        request::HttpRequest,
+        response::{HttpResponse, IntoResponse},
+        status::StatusCode,
+    },
+};
+
+#[cfg(feature = "serde")]
+pub use crate::http::json::Json;
+
+#[cfg(feature = "serde")]
+use crate::http::{deserializer, form::FormData, json::JsonError, shared::{HttpBodyType, HttpHeaderBody}};
+
+/// Values a handler can take as arguments, pulled from the request before the handler runs,
+///     e.g. `|Path(id): Path<u64>, Query(page): Query<Page>| ..`
+///     A failed extraction answers the request with its rejection, and the handler is not run.
+pub trait FromRequest<S>: Sized {
+    /// Extracts the value from the request
+    ///
+    /// # Arguments
+    ///
+    /// * `http_req` - Request with its path parameters already filled by the router
+    /// * `state` - State of the router, see HttpServer::with_state
+    fn from_request(http_req: &HttpRequest, state: &Arc<S>) -> Result<Self, ExtractRejection>;
+}
+
+/// Failure to extract a handler argument, answered with its status and message as a text/plain body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractRejection {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ExtractRejection {
+    pub fn new(status: StatusCode, message: String) -> ExtractRejection {
+        ExtractRejection {
+            status,
+            message,
+        }
+    }
+
+    /// 400 Bad Request, the request itself is malformed
+    pub fn bad_request(message: String) -> ExtractRejection {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+}
+
+impl std::fmt::Display for ExtractRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for ExtractRejection {}
+
+impl IntoResponse for ExtractRejection {
+    fn into_response(self) -> HttpResponse {
+        (self.status, self.message).into_response()
+    }
+}
+
+/// None instead of a rejection, for arguments the request may leave out
+impl<S, T: FromRequest<S>> FromRequest<S> for Option<T> {
+    fn from_request(http_req: &HttpRequest, state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        Ok(T::from_request(http_req, state).ok())
+    }
+}
+
+impl<S> FromRequest<S> for State<S> {
+    fn from_request(_http_req: &HttpRequest, state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        Ok(State(Arc::clone(state)))
+    }
+}
+
+/// Body exactly as received, empty for multipart/form-data, see HttpRequest::body_bytes
+#[derive(Debug, Clone)]
+pub struct Bytes(pub Vec<u8>);
+
+impl<S> FromRequest<S> for Bytes {
+    fn from_request(http_req: &HttpRequest, _state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        Ok(Bytes(http_req.body_bytes().to_vec()))
+    }
+}
+
+/// IP address of the client, rejected with 500 if the socket could not report it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerAddr(pub IpAddr);
+
+impl<S> FromRequest<S> for PeerAddr {
+    fn from_request(http_req: &HttpRequest, _state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        http_req.peer_addr.as_deref()
+            .and_then(|addr| addr.parse::<IpAddr>().ok())
+            .map(PeerAddr)
+            .ok_or_else(|| ExtractRejection::new(StatusCode::INTERNAL_SERVER_ERROR, "Peer address unavailable".to_string()))
+    }
+}
+
+/// Header field decoded into a type, see Header
+pub trait TypedHeader: Sized {
+    /// Field name, matched case-insensitively
+    const NAME: &'static str;
+
+    /// Decodes the first value of the field, the error is sent back in the 400 body
+    fn decode(value: &str) -> Result<Self, String>;
+}
+
+/// Header field of the request, e.g. `Header(UserAgent(agent)): Header<UserAgent>`
+///     Rejected with 400 if the field is missing or fails to decode, take `Option<Header<T>>` for optional fields.
+#[derive(Debug, Clone)]
+pub struct Header<T>(pub T);
+
+impl<S, T: TypedHeader> FromRequest<S> for Header<T> {
+    fn from_request(http_req: &HttpRequest, _state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        let value = http_req.headers.get(T::NAME)
+            .ok_or_else(|| ExtractRejection::bad_request(format!("Missing header \"{}\"", T::NAME)))?;
+        T::decode(value)
+            .map(Header)
+            .map_err(|e| ExtractRejection::bad_request(format!("Invalid header \"{}\": {}", T::NAME, e)))
+    }
+}
+
+macro_rules! text_headers {
+    ($($(#[$doc:meta])* $name:ident => $field:literal;)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Clone, PartialEq, Eq)]
+            pub struct $name(pub String);
+
+            impl TypedHeader for $name {
+                const NAME: &'static str = $field;
+
+                fn decode(value: &str) -> Result<Self, String> {
+                    Ok($name(value.to_string()))
+                }
+            }
+        )*
+    };
+}
+
+text_headers! {
+    /// Content-Type as sent, parameters included
+    ContentType => "Content-Type";
+    UserAgent => "User-Agent";
+    /// Authorization credentials as sent, scheme included
+    Authorization => "Authorization";
+    Host => "Host";
+    Accept => "Accept";
+}
+
+/// Content-Length of the body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLength(pub u64);
+
+impl TypedHeader for ContentLength {
+    const NAME: &'static str = "Content-Length";
+
+    fn decode(value: &str) -> Result<Self, String> {
+        value.trim().parse::<u64>()
+            .map(ContentLength)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Path parameters deserialized into a type, rejected with 400 if they do not fit.
+///     Structs are filled by name, e.g. `Path<Ids>` for `/person/{person_id}/pet/{pet_id}`,
+///     tuples by position, e.g. `Path<(u64, u64)>`, and single values from the only parameter, e.g. `Path<u64>`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone)]
+pub struct Path<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<S, T: serde::de::DeserializeOwned> FromRequest<S> for Path<T> {
+    fn from_request(http_req: &HttpRequest, _state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        deserializer::from_pairs(http_req.path_params().iter())
+            .map(Path)
+            .map_err(|e| ExtractRejection::bad_request(format!("Invalid path parameters: {}", e)))
+    }
+}
+
+/// Query string deserialized into a type, rejected with 400 if it does not fit.
+///     Repeated keys and `key[]` fill Vec fields, and Option fields may be left out.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone)]
+pub struct Query<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<S, T: serde::de::DeserializeOwned> FromRequest<S> for Query<T> {
+    fn from_request(http_req: &HttpRequest, _state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        deserializer::from_pairs(http_req.query().iter())
+            .map(Query)
+            .map_err(|e| ExtractRejection::bad_request(format!("Invalid query string: {}", e)))
+    }
+}
+
+/// Url-encoded or multipart form fields deserialized into a type, file uploads are only available as parts.
+///     Rejected with 415 if the body is not a form, or 422 if the fields do not fit.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone)]
+pub struct Form<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<S, T: serde::de::DeserializeOwned> FromRequest<S> for Form<T> {
+    fn from_request(http_req: &HttpRequest, _state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        if !matches!(http_req.body.body_type, Some(HttpBodyType::UrlEncoded | HttpBodyType::FormData)) {
+            return Err(unsupported_media_type(http_req, "application/x-www-form-urlencoded or multipart/form-data"));
+        }
+        // an empty form has no fields parsed
+        let empty = FormData::new();
+        let form = http_req.form().unwrap_or(&empty);
+        deserializer::from_pairs(form.iter())
+            .map(Form)
+            .map_err(|e| ExtractRejection::new(StatusCode::UNPROCESSABLE_CONTENT, format!("Invalid form: {}", e)))
+    }
+}
+
+/// JSON body deserialized into a type.
+///     Rejected with 415 if the Content-Type is not JSON, 400 if the body is not valid JSON, or 422 if it does not fit the type.
+#[cfg(feature = "serde")]
+impl<S, T: serde::de::DeserializeOwned> FromRequest<S> for Json<T> {
+    fn from_request(http_req: &HttpRequest, _state: &Arc<S>) -> Result<Self, ExtractRejection> {
+        if !matches!(http_req.body.body_type, Some(HttpBodyType::Json)) {
+            return Err(unsupported_media_type(http_req, "application/json"));
+        }
+        serde_json::from_slice(http_req.body_bytes())
+            .map(Json)
+            .map_err(|e| {
+                let status = match e.classify() {
+                    serde_json::error::Category::Data => StatusCode::UNPROCESSABLE_CONTENT,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                ExtractRejection::new(status, format!("Invalid JSON body: {}", JsonError::from(e)))
+            })
+    }
+}
+
+#[cfg(feature = "serde")]
+fn unsupported_media_type(http_req: &HttpRequest, expected: &str) -> ExtractRejection {
+    let content_type = http_req.headers.get("Content-Type")
+        .map(|content_type| HttpHeaderBody::parse_content_type(content_type).0)
+        .unwrap_or_else(|| "none".to_string());
+    ExtractRejection::new(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        format!("Expected Content-Type {}, found {}", expected, content_type),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{Header, UserAgent};
+    use crate::{
+        http::{
+            limits::{ServerLimits, ServerTimeouts},
+            request::{HttpRequest, HttpRequestMethod},
+            status::StatusCode,
+        },
+        router::HttpRouter,
+    };
+
+    /// Status the router answers the raw request with
+    async fn status(router: &Arc<HttpRouter>, raw: &str) -> StatusCode {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        client.write_all(raw.as_bytes()).await.unwrap();
+        client.shutdown().await.unwrap();
+        let http_req = match HttpRequest::new(&mut BufReader::new(server), &ServerLimits::default(), &ServerTimeouts::default()).await {
+            Ok(http_req) => http_req,
+            Err(failure) => panic!("{}", failure.fail_reason),
+        };
+        Arc::clone(router).handle_request(http_req).await.status
+    }
+
+    #[tokio::test]
+    async fn rejects_a_missing_header_with_400() {
+        let mut router = HttpRouter::new();
+        router.add_handler(HttpRequestMethod::Get, "/agent", |Header(UserAgent(agent)): Header<UserAgent>| agent);
+        let router = Arc::new(router);
+        assert_eq!(status(&router, "GET /agent HTTP/1.1\r\nUser-Agent: test\r\n\r\n").await, StatusCode::OK);
+        assert_eq!(status(&router, "GET /agent HTTP/1.1\r\n\r\n").await, StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(feature = "serde")]
+    mod serde_extractors {
+        use std::sync::Arc;
+
+        use super::status;
+        use crate::{
+            extract::{Form, Json, Path, Query},
+            http::{request::HttpRequestMethod, status::StatusCode},
+            router::HttpRouter,
+        };
+
+        #[derive(serde::Deserialize, serde::Serialize)]
+        struct Person {
+            id: u64,
+            name: String,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Page {
+            page: Option<u32>,
+        }
+
+        /// Request with a body of the content type
+        fn with_body(method: &str, path: &str, content_type: &str, body: &str) -> String {
+            format!("{} {} HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}", method, path, content_type, body.len(), body)
+        }
+
+        fn router() -> Arc<HttpRouter> {
+            let mut router = HttpRouter::new();
+            router.add_handler(HttpRequestMethod::Get, "/person/{id}", |Path(id): Path<u64>| id.to_string());
+            router.add_handler(HttpRequestMethod::Get, "/pet/{id}/{name}", |Path((id, name)): Path<(u32, String)>| format!("{} {}", id, name));
+            router.add_handler(HttpRequestMethod::Get, "/people", |Query(page): Query<Page>| page.page.unwrap_or_default().to_string());
+            router.add_handler(HttpRequestMethod::Post, "/form", |Form(person): Form<Person>| person.name);
+            router.add_handler(HttpRequestMethod::Post, "/json", |Json(person): Json<Person>| Json(person));
+            Arc::new(router)
+        }
+
+        #[tokio::test]
+        async fn rejects_bad_path_and_query_with_400() {
+            let router = router();
+            assert_eq!(status(&router, "GET /person/42 HTTP/1.1\r\n\r\n").await, StatusCode::OK);
+            assert_eq!(status(&router, "GET /person/abc HTTP/1.1\r\n\r\n").await, StatusCode::BAD_REQUEST);
+            assert_eq!(status(&router, "GET /pet/7/rex HTTP/1.1\r\n\r\n").await, StatusCode::OK);
+            assert_eq!(status(&router, "GET /pet/x/rex HTTP/1.1\r\n\r\n").await, StatusCode::BAD_REQUEST);
+            assert_eq!(status(&router, "GET /people?page=2 HTTP/1.1\r\n\r\n").await, StatusCode::OK);
+            assert_eq!(status(&router, "GET /people HTTP/1.1\r\n\r\n").await, StatusCode::OK);
+            assert_eq!(status(&router, "GET /people?page=two HTTP/1.1\r\n\r\n").await, StatusCode::BAD_REQUEST);
+        }
+
+        #[tokio::test]
+        async fn rejects_wrong_content_type_with_415() {
+            let router = router();
+            assert_eq!(status(&router, &with_body("POST", "/form", "application/json", "{}")).await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            assert_eq!(status(&router, &with_body("POST", "/json", "application/x-www-form-urlencoded", "id=1")).await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            assert_eq!(status(&router, "POST /json HTTP/1.1\r\nContent-Length: 0\r\n\r\n").await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+
+        #[tokio::test]
+        async fn rejects_bodies_that_do_not_fit_with_422() {
+            let router = router();
+            let form = "application/x-www-form-urlencoded";
+            assert_eq!(status(&router, &with_body("POST", "/form", form, "id=1&name=John")).await, StatusCode::OK);
+            assert_eq!(status(&router, &with_body("POST", "/form", form, "id=abc&name=John")).await, StatusCode::UNPROCESSABLE_CONTENT);
+            assert_eq!(status(&router, &with_body("POST", "/form", form, "")).await, StatusCode::UNPROCESSABLE_CONTENT);
+
+            let json = "application/json";
+            assert_eq!(status(&router, &with_body("POST", "/json", json, r#"{"id": 1, "name": "John"}"#)).await, StatusCode::OK);
+            assert_eq!(status(&router, &with_body("POST", "/json", json, r#"{"id": "1", "name": "John"}"#)).await, StatusCode::UNPROCESSABLE_CONTENT);
+            // not JSON at all is malformed rather than unfit
+            assert_eq!(status(&router, &with_body("POST", "/json", json, "{\"id\": ")).await, StatusCode::BAD_REQUEST);
+        }
+    }
+}
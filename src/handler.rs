@@ -0,0 +1,207 @@
+use std::{future::Future, ops::Deref, pin::Pin, sync::Arc};
+
+use crate::{
+    extract::{ExtractRejection, FromRequest},
+    http::{
+        request::HttpRequest,
+        response::{HttpResponse, IntoResponse},
+    },
+};
+
+/// Boxed future that can be awaited on any worker thread of the runtime
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Handler with its kind erased, ready to be run by the middleware pipeline
+pub(crate) type Endpoint = Arc<dyn Fn(HttpRequest) -> BoxFuture<'static, HttpResponse> + Send + Sync>;
+
+/// Handler with its kind erased, as stored by a router with state S
+pub(crate) type RouteEndpoint<S> = Arc<dyn Fn(HttpRequest, &Arc<S>) -> BoxFuture<'static, HttpResponse> + Send + Sync>;
+
+/// Application state shared by every handler, see HttpServer::with_state
+///     Derefs to the state, and clones share the same state.
+#[derive(Debug)]
+pub struct State<S>(pub Arc<S>);
+
+impl<S> Clone for State<S> {
+    fn clone(&self) -> Self {
+        State(Arc::clone(&self.0))
+    }
+}
+
+impl<S> Deref for State<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0
+    }
+}
+
+/// Marks handlers of the form `fn(&mut HttpRequest) -> impl IntoResponse`
+pub struct SyncHandler;
+
+/// Marks handlers of the form `async fn(HttpRequest) -> impl IntoResponse`
+pub struct AsyncHandler;
+
+/// Marks handlers that also take the state, `fn(&mut HttpRequest, &S)` or `async fn(HttpRequest, State<S>)`
+pub struct WithState;
+
+/// Marks handlers taking extractors instead of the request, e.g. `fn(Path<u64>, Json<Person>)`, see FromRequest
+pub struct Extract;
+
+/// Functions that can handle a route of a router with state S, either sync or async, with or without the state.
+///     Sync handlers run on the blocking thread pool, so they may block on IO without holding up other connections.
+///     Async handlers are awaited on the runtime HttpServer::start builds, so they must not block.
+///     A handler taking a state of another type than the router's does not implement Handler, so it fails to compile.
+///     Handlers may instead take up to six extractors, e.g. `|Path(id): Path<u64>, State(state): State<S>|`,
+///     which are pulled from the request in order before the handler runs, see FromRequest.
+///     HttpRouter::add_route takes only `fn(&mut HttpRequest)`, so its closures need no annotations, every kind is added with HttpRouter::add_handler.
+///     Closures given to add_handler must name their argument types, e.g. `|http_request: HttpRequest| async move {..}`,
+///     as they can not be inferred when every kind is accepted.
+pub trait Handler<S, Kind>: Send + Sync + 'static {
+    #[doc(hidden)]
+    fn into_endpoint(self) -> RouteEndpoint<S>;
+}
+
+impl<S, F, R> Handler<S, (SyncHandler, R)> for F
+where
+    S: Send + Sync + 'static,
+    F: Fn(&mut HttpRequest) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    fn into_endpoint(self) -> RouteEndpoint<S> {
+        let handler = move |http_req: &mut HttpRequest, _: &S| self(http_req);
+        Handler::<S, (SyncHandler, WithState, R)>::into_endpoint(handler)
+    }
+}
+
+impl<S, F, R> Handler<S, (SyncHandler, WithState, R)> for F
+where
+    S: Send + Sync + 'static,
+    F: Fn(&mut HttpRequest, &S) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    fn into_endpoint(self) -> RouteEndpoint<S> {
+        let handler = Arc::new(self);
+        Arc::new(move |mut http_req: HttpRequest, state: &Arc<S>| {
+            let handler = Arc::clone(&handler);
+            let state = Arc::clone(state);
+            Box::pin(run_blocking(move || {
+                handler(&mut http_req, &state).respond_to(&mut http_req);
+                if !http_req.responded() {
+                    log::debug!("Handler for {} {} did not respond! Responding with OK", http_req.route.method, http_req.route.path);
+                }
+                http_req.take_response().unwrap_or_else(HttpResponse::ok)
+            }))
+        })
+    }
+}
+
+impl<S, F, Fut> Handler<S, (AsyncHandler, Fut)> for F
+where
+    S: Send + Sync + 'static,
+    F: Fn(HttpRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    fn into_endpoint(self) -> RouteEndpoint<S> {
+        Arc::new(move |http_req: HttpRequest, _: &Arc<S>| {
+            let future = self(http_req);
+            Box::pin(async move { future.await.into_response() })
+        })
+    }
+}
+
+impl<S, F, Fut> Handler<S, (AsyncHandler, WithState, Fut)> for F
+where
+    S: Send + Sync + 'static,
+    F: Fn(HttpRequest, State<S>) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    fn into_endpoint(self) -> RouteEndpoint<S> {
+        Arc::new(move |http_req: HttpRequest, state: &Arc<S>| {
+            let future = self(http_req, State(Arc::clone(state)));
+            Box::pin(async move { future.await.into_response() })
+        })
+    }
+}
+
+macro_rules! extract_handlers {
+    ($($extractor:ident),*) => {
+        impl<S, F, R, $($extractor,)*> Handler<S, (SyncHandler, Extract, ($($extractor,)*), R)> for F
+        where
+            S: Send + Sync + 'static,
+            F: Fn($($extractor),*) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($extractor: FromRequest<S> + Send + 'static,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn into_endpoint(self) -> RouteEndpoint<S> {
+                let handler = Arc::new(self);
+                Arc::new(move |http_req: HttpRequest, state: &Arc<S>| {
+                    $(
+                        let $extractor = match $extractor::from_request(&http_req, state) {
+                            Ok(value) => value,
+                            Err(rejection) => return rejected(&http_req, rejection),
+                        };
+                    )*
+                    let handler = Arc::clone(&handler);
+                    Box::pin(run_blocking(move || handler($($extractor),*).into_response()))
+                })
+            }
+        }
+
+        impl<S, F, Fut, $($extractor,)*> Handler<S, (AsyncHandler, Extract, ($($extractor,)*), Fut)> for F
+        where
+            S: Send + Sync + 'static,
+            F: Fn($($extractor),*) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            $($extractor: FromRequest<S>,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn into_endpoint(self) -> RouteEndpoint<S> {
+                Arc::new(move |http_req: HttpRequest, state: &Arc<S>| {
+                    $(
+                        let $extractor = match $extractor::from_request(&http_req, state) {
+                            Ok(value) => value,
+                            Err(rejection) => return rejected(&http_req, rejection),
+                        };
+                    )*
+                    let future = self($($extractor),*);
+                    Box::pin(async move { future.await.into_response() })
+                })
+            }
+        }
+    };
+}
+
+extract_handlers!();
+extract_handlers!(T1);
+extract_handlers!(T1, T2);
+extract_handlers!(T1, T2, T3);
+extract_handlers!(T1, T2, T3, T4);
+extract_handlers!(T1, T2, T3, T4, T5);
+extract_handlers!(T1, T2, T3, T4, T5, T6);
+
+/// Runs a sync handler on the blocking thread pool, carrying its panic to the connection
+async fn run_blocking<F>(handler: F) -> HttpResponse
+where
+    F: FnOnce() -> HttpResponse + Send + 'static,
+{
+    match tokio::task::spawn_blocking(handler).await {
+        Ok(http_res) => http_res,
+        // the connection answers the panic with a 500
+        Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+        Err(error) => {
+            log::error!("Handler was cancelled!\n\t{}", error);
+            HttpResponse::error()
+        }
+    }
+}
+
+/// Answers a request whose extractor failed, without running its handler
+fn rejected(http_req: &HttpRequest, rejection: ExtractRejection) -> BoxFuture<'static, HttpResponse> {
+    log::debug!("Rejected {} {}: {}", http_req.route.method, http_req.route.path, rejection);
+    Box::pin(std::future::ready(rejection.into_response()))
+}
@@ -0,0 +1,254 @@
+use tokio::{
+    io::{AsyncReadExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::LineOrError;
+
+use super::{
+    headers::HttpHeaders,
+    limits::{LimitedLineReader, LineReadError, ServerLimits},
+    multipart::MultipartParser,
+    request::{HttpRequestFailure, HttpRequestParser},
+    status::StatusCode,
+};
+
+/// Decodes `Transfer-Encoding: chunked` message bodies, per RFC 9112 7.1
+pub struct ChunkedDecoder;
+
+impl ChunkedDecoder {
+    /// Reads every chunk up to and including the trailer section.
+    ///     Returns the decoded body and any trailer fields, the body is empty if each chunk was fed to a multipart parser.
+    pub async fn read_body(buf_reader: &mut BufReader<TcpStream>, limits: &ServerLimits, mut multipart: Option<&mut MultipartParser>) -> Result<(Vec<u8>, HttpHeaders), HttpRequestFailure> {
+        let mut body: Vec<u8> = Vec::new();
+        let mut received: usize = 0;
+        loop {
+            let size_line = Self::read_line(buf_reader, limits.max_request_line, StatusCode::BAD_REQUEST).await?;
+            let chunk_size = Self::parse_chunk_size(&size_line)?;
+            if chunk_size == 0 {
+                break;
+            }
+            if chunk_size > limits.max_body - received {
+                return Err(HttpRequestFailure::new(StatusCode::CONTENT_TOO_LARGE, format!("Chunked body is over {} bytes", limits.max_body)));
+            }
+
+            let start = body.len();
+            body.resize(start + chunk_size, 0);
+            if let Err(e) = buf_reader.read_exact(&mut body[start..]).await {
+                return Err(format!("Connection closed before the chunk was received!\n\t{}", e).into());
+            }
+            if !Self::read_line(buf_reader, 0, StatusCode::BAD_REQUEST).await?.is_empty() {
+                return Err("Bad request! Chunk data is not followed by CRLF".to_string().into());
+            }
+            received += chunk_size;
+            if let Some(parser) = multipart.as_deref_mut() {
+                parser.push(&body[start..]).await?;
+                body.truncate(start);
+            }
+        }
+
+        let trailers = Self::read_trailers(buf_reader, limits).await?;
+        Ok((body, trailers))
+    }
+
+    /// Chunk size in hex, chunk extensions after `;` are ignored
+    fn parse_chunk_size(line: &str) -> Result<usize, HttpRequestFailure> {
+        let size = line.split(';').next().unwrap_or_default().trim_end_matches([' ', '\t']);
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("Bad request! Invalid chunk size: {}", line).into());
+        }
+        usize::from_str_radix(size, 16).map_err(|e| format!("Bad request! Invalid chunk size: {} {}", line, e).into())
+    }
+
+    /// Reads the trailer section, bounded by the header limits, which the caller reduces by what the header used
+    async fn read_trailers(buf_reader: &mut BufReader<TcpStream>, limits: &ServerLimits) -> Result<HttpHeaders, HttpRequestFailure> {
+        let mut lines: Vec<LineOrError> = Vec::new();
+        let mut trailer_bytes: usize = 0;
+        loop {
+            let max_len = limits.max_header_bytes - trailer_bytes;
+            let line = Self::read_line(buf_reader, max_len, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE).await?;
+            if line.is_empty() {
+                break;
+            }
+            trailer_bytes += line.len();
+            if lines.len() >= limits.max_headers {
+                return Err(HttpRequestFailure::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, format!("More than {} trailers were sent after the header", limits.max_headers)));
+            }
+            lines.push(LineOrError::Line(line));
+        }
+        Ok(HttpRequestParser::fields(&lines)?)
+    }
+
+    /// Reads a line without its line ending, failing if the connection closes first
+    ///
+    /// # Arguments
+    ///
+    /// * `too_long` - Status to answer with if the line is over max_len
+    async fn read_line(buf_reader: &mut BufReader<TcpStream>, max_len: usize, too_long: StatusCode) -> Result<String, HttpRequestFailure> {
+        match LimitedLineReader::read_line(buf_reader, max_len).await {
+            Ok(Some(line)) => Ok(line),
+            Ok(None) | Err(LineReadError::UnexpectedEof) => Err("Connection closed before the chunked body ended!".to_string().into()),
+            Err(LineReadError::TooLong) => Err(HttpRequestFailure::new(too_long, format!("Line in chunked body is over {} bytes", max_len))),
+            Err(LineReadError::Io(e)) => Err(e.to_string().into()),
+        }
+    }
+}
+
+/// Encodes response bodies as `Transfer-Encoding: chunked`
+pub struct ChunkedEncoder;
+
+impl ChunkedEncoder {
+    /// Zero sized chunk ending the body, with no trailers
+    pub const LAST_CHUNK: &'static [u8] = b"0\r\n\r\n";
+
+    /// Frames data as a single chunk, empty data must not be framed as it would end the body
+    pub fn encode_chunk(data: &[u8]) -> Vec<u8> {
+        let mut chunk: Vec<u8> = format!("{:X}\r\n", data.len()).into_bytes();
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(b"\r\n");
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::ChunkedDecoder;
+    use crate::http::{
+        limits::{ServerLimits, ServerTimeouts},
+        request::{HttpRequest, HttpRequestFailure},
+        status::StatusCode,
+    };
+
+    /// Server side of a loopback connection the client has written the bytes to and closed
+    async fn connection(bytes: &[u8]) -> BufReader<TcpStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        client.write_all(bytes).await.unwrap();
+        client.shutdown().await.unwrap();
+        BufReader::new(server)
+    }
+
+    fn status<T>(result: Result<T, HttpRequestFailure>) -> Option<StatusCode> {
+        result.err().map(|failure| failure.status)
+    }
+
+    #[test]
+    fn parses_chunk_sizes() {
+        assert_eq!(ChunkedDecoder::parse_chunk_size("0").ok(), Some(0));
+        assert_eq!(ChunkedDecoder::parse_chunk_size("1a").ok(), Some(26));
+        assert_eq!(ChunkedDecoder::parse_chunk_size("FF").ok(), Some(255));
+        assert_eq!(ChunkedDecoder::parse_chunk_size("5;name=value").ok(), Some(5));
+        assert_eq!(ChunkedDecoder::parse_chunk_size("5 \t;name").ok(), Some(5));
+    }
+
+    #[test]
+    fn rejects_bad_chunk_sizes() {
+        for size in ["", ";ext", "xyz", "-1", "+5", " 5", "0x5", "ffffffffffffffffffffffff"] {
+            assert_eq!(status(ChunkedDecoder::parse_chunk_size(size)), Some(StatusCode::BAD_REQUEST), "{:?}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_chunks_and_trailers() {
+        let mut reader = connection(b"5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nX-Checksum: abc\r\nX-Other:  1 \r\n\r\nnext").await;
+        let (body, trailers) = match ChunkedDecoder::read_body(&mut reader, &ServerLimits::default(), None).await {
+            Ok(decoded) => decoded,
+            Err(failure) => panic!("{}", failure.fail_reason),
+        };
+        assert_eq!(body, b"hello, world");
+        assert_eq!(trailers.get("x-checksum"), Some("abc"));
+        assert_eq!(trailers.get("X-Other"), Some("1"));
+        // bytes after the body are left for the next request
+        assert_eq!(reader.buffer(), b"next");
+    }
+
+    #[tokio::test]
+    async fn rejects_chunk_data_without_crlf() {
+        let mut reader = connection(b"5\r\nhelloXX\r\n0\r\n\r\n").await;
+        let result = ChunkedDecoder::read_body(&mut reader, &ServerLimits::default(), None).await;
+        assert_eq!(status(result), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_chunk_size_line() {
+        let mut reader = connection(b"zz\r\nhello\r\n0\r\n\r\n").await;
+        let result = ChunkedDecoder::read_body(&mut reader, &ServerLimits::default(), None).await;
+        assert_eq!(status(result), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn rejects_body_closed_early() {
+        let mut reader = connection(b"5\r\nhel").await;
+        let result = ChunkedDecoder::read_body(&mut reader, &ServerLimits::default(), None).await;
+        assert_eq!(status(result), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn rejects_body_over_limit() {
+        let limits = ServerLimits {
+            max_body: 8,
+            ..ServerLimits::default()
+        };
+        let mut reader = connection(b"5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n").await;
+        let result = ChunkedDecoder::read_body(&mut reader, &limits, None).await;
+        assert_eq!(status(result), Some(StatusCode::CONTENT_TOO_LARGE));
+    }
+
+    async fn request(raw: &[u8]) -> Result<HttpRequest, HttpRequestFailure> {
+        request_with(raw, &ServerLimits::default()).await
+    }
+
+    async fn request_with(raw: &[u8], limits: &ServerLimits) -> Result<HttpRequest, HttpRequestFailure> {
+        let mut reader = connection(raw).await;
+        HttpRequest::new(&mut reader, limits, &ServerTimeouts::default()).await
+    }
+
+    #[tokio::test]
+    async fn decodes_chunked_request() {
+        let http_req = match request(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\nX-Done: yes\r\n\r\n").await {
+            Ok(http_req) => http_req,
+            Err(failure) => panic!("{}", failure.fail_reason),
+        };
+        assert_eq!(http_req.body_bytes(), b"hi");
+        assert_eq!(http_req.trailers.get("X-Done"), Some("yes"));
+    }
+
+    #[tokio::test]
+    async fn rejects_transfer_encoding_with_content_length() {
+        let result = request(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 2\r\n\r\n2\r\nhi\r\n0\r\n\r\n").await;
+        assert_eq!(status(result), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn rejects_codings_other_than_chunked() {
+        for coding in ["gzip, chunked", "chunked, chunked", "gzip", "identity"] {
+            let raw = format!("POST /a HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n2\r\nhi\r\n0\r\n\r\n", coding);
+            assert_eq!(status(request(raw.as_bytes()).await), Some(StatusCode::NOT_IMPLEMENTED), "{}", coding);
+        }
+    }
+
+    #[tokio::test]
+    async fn trailers_share_the_header_limits() {
+        let limits = ServerLimits {
+            max_headers: 2,
+            ..ServerLimits::default()
+        };
+        let raw = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-One: 1\r\n\r\n";
+        assert!(request_with(raw, &limits).await.is_ok());
+        let raw = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-One: 1\r\nX-Two: 2\r\n\r\n";
+        assert_eq!(status(request_with(raw, &limits).await), Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+
+        let limits = ServerLimits {
+            max_header_bytes: 40,
+            ..ServerLimits::default()
+        };
+        let raw = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-Long-Trailer: 0123456789\r\n\r\n";
+        assert_eq!(status(request_with(raw, &limits).await), Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+    }
+}
@@ -0,0 +1,306 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::watch,
+};
+
+use crate::router::HttpRouter;
+
+use super::{
+    chunked::ChunkedEncoder,
+    limits::{ServerLimits, ServerTimeouts},
+    request::{HttpRequest, HttpRequestFailure, HttpRequestMethod, HttpVersion},
+    response::HttpResponse,
+    status::StatusCode,
+};
+
+/// Settings shared by every connection of an HttpServer
+pub struct ConnectionConfig {
+    /// Max time to wait for the next request on an idle persistent connection
+    pub keep_alive_timeout: Duration,
+    /// Max requests served on a single connection before it is closed
+    pub max_requests: usize,
+    pub limits: ServerLimits,
+    pub timeouts: ServerTimeouts,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests: 100,
+            limits: ServerLimits::default(),
+            timeouts: ServerTimeouts::default(),
+        }
+    }
+}
+
+pub struct HttpConnection<S = ()> {
+    reader: BufReader<TcpStream>,
+    router: Arc<HttpRouter<S>>,
+    config: Arc<ConnectionConfig>,
+    shutdown_rx: watch::Receiver<bool>,
+}
+
+impl<S> HttpConnection<S>
+where
+    S: Send + Sync + 'static,
+{
+    pub fn new(stream: TcpStream, router: Arc<HttpRouter<S>>, config: Arc<ConnectionConfig>, shutdown_rx: watch::Receiver<bool>) -> HttpConnection<S> {
+        HttpConnection {
+            reader: BufReader::new(stream),
+            router,
+            config,
+            shutdown_rx,
+        }
+    }
+
+    /// Serves requests on the connection until either side closes it.
+    ///     Requests are handled one at a time, so pipelined requests are answered in order.
+    pub async fn serve(mut self) {
+        let mut served: usize = 0;
+        loop {
+            if !self.wait_for_request(served == 0).await {
+                break;
+            }
+
+            let start_time = std::time::Instant::now();
+            let h_req: Result<HttpRequest, HttpRequestFailure> = HttpRequest::new(&mut self.reader, &self.config.limits, &self.config.timeouts).await;
+            let elapsed = start_time.elapsed();
+            log::debug!("Request parsing took {} microseconds", elapsed.as_micros());
+            served += 1;
+
+            match h_req {
+                Ok(http_req) => {
+                    let version: HttpVersion = http_req.version;
+                    let client_keep_alive: bool = http_req.keep_alive();
+                    let is_head: bool = http_req.route.method == HttpRequestMethod::Head;
+                    let mut http_res: HttpResponse = self.run_handler(http_req).await;
+
+                    // HTTP/1.0 has no chunked framing, so a streamed body ends by closing the connection
+                    let keep_alive = client_keep_alive
+                        && served < self.config.max_requests
+                        && !*self.shutdown_rx.borrow()
+                        && !(http_res.stream.is_some() && version == HttpVersion::Http10);
+                    match (keep_alive, version) {
+                        (false, _) => http_res.headers.insert("Connection", "close"),
+                        (true, HttpVersion::Http10) => http_res.headers.insert("Connection", "keep-alive"),
+                        (true, HttpVersion::Http11) => (),
+                    };
+
+                    let written = match is_head {
+                        true => self.write(&http_res.to_head_bytes()).await,
+                        false => self.write_response(http_res, version).await,
+                    };
+                    if !written || !keep_alive {
+                        break;
+                    }
+                },
+                Err(http_fail) => {
+                    log::error!("Error occured from HttpRequest: \n\t{}", http_fail.fail_reason);
+                    // the rest of the stream can not be trusted to start a new request
+                    self.write(&HttpResponse::new(http_fail.status).with_header("Connection", "close").to_bytes()).await;
+                    break;
+                }
+            }
+        }
+
+        if let Err(e) = self.reader.get_mut().shutdown().await {
+            log::debug!("Failed to shutdown TcpStream!\n\t{}", e);
+        }
+    }
+
+    /// Runs the router in its own task, so a panicking handler is answered with a 500 instead of dropping the connection.
+    ///     Handlers running past the handler timeout have their response replaced with a 503,
+    ///     async handlers are cancelled while sync handlers are left to finish on their blocking thread.
+    async fn run_handler(&self, http_req: HttpRequest) -> HttpResponse {
+        let method = http_req.route.method.clone();
+        let path = http_req.route.path.clone();
+        let mut handler = tokio::spawn(Arc::clone(&self.router).handle_request(http_req));
+
+        match tokio::time::timeout(self.config.timeouts.handler, &mut handler).await {
+            Ok(Ok(http_res)) => http_res,
+            Ok(Err(error)) => {
+                log::error!("Handler for {} {} panicked!\n\t{}", method, path, error);
+                HttpResponse::error()
+            },
+            Err(_) => {
+                handler.abort();
+                log::warn!("Handler for {} {} did not finish within {} seconds", method, path, self.config.timeouts.handler.as_secs());
+                HttpResponse::new(StatusCode::SERVICE_UNAVAILABLE)
+            }
+        }
+    }
+
+    /// Waits until the next request starts arriving.
+    ///     Returns false if the client closed the connection, it sat idle too long, or the server is shutting down.
+    ///
+    /// # Arguments
+    ///
+    /// * `first_request` - Whether the connection is new, it then gets the header timeout and is answered with 408 if it sends nothing
+    async fn wait_for_request(&mut self, first_request: bool) -> bool {
+        // pipelined requests may already be buffered
+        if !self.reader.buffer().is_empty() {
+            return true;
+        }
+
+        let idle_timeout = match first_request {
+            true => self.config.timeouts.header_read,
+            false => self.config.keep_alive_timeout,
+        };
+        let idle = tokio::time::timeout(idle_timeout, self.reader.fill_buf());
+        let filled = tokio::select! {
+            filled = idle => filled,
+            _ = self.shutdown_rx.wait_for(|shutdown| *shutdown) => return false,
+        };
+        match filled {
+            Ok(Ok(buf)) => !buf.is_empty(),
+            Ok(Err(e)) => {
+                log::debug!("Failed to read from TcpStream!\n\t{}", e);
+                false
+            },
+            Err(_) if first_request => {
+                log::warn!("Request was not received within {} seconds", idle_timeout.as_secs());
+                self.write(&HttpResponse::new(StatusCode::REQUEST_TIMEOUT).with_header("Connection", "close").to_bytes()).await;
+                false
+            },
+            Err(_) => {
+                log::debug!("Closing connection idle for over {} seconds", idle_timeout.as_secs());
+                false
+            }
+        }
+    }
+
+    /// Writes the response, streaming its body if it has one.
+    ///     Returns false if the connection can not be reused.
+    async fn write_response(&mut self, http_res: HttpResponse, version: HttpVersion) -> bool {
+        let receiver = match &http_res.stream {
+            Some(stream) => stream.take(),
+            None => return self.write(&http_res.to_bytes()).await,
+        };
+        let mut receiver = match receiver {
+            Some(receiver) => receiver,
+            None => {
+                log::error!("Response stream was already written! Closing connection.");
+                return false;
+            }
+        };
+
+        let chunked = version == HttpVersion::Http11;
+        if !self.write(&http_res.to_stream_head(chunked)).await {
+            return false;
+        }
+        if http_res.status.forbids_body() {
+            return true;
+        }
+
+        // awaiting each write holds the next chunk in the channel, pushing back on the producer
+        let write_timeout = self.config.timeouts.write;
+        loop {
+            let chunk = match tokio::time::timeout(write_timeout, receiver.recv()).await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(_) => {
+                    // leaving the body unterminated tells the client it is incomplete
+                    log::warn!("Response stream sent nothing within {} seconds! Closing connection without ending the body.", write_timeout.as_secs());
+                    return false;
+                }
+            };
+            match chunk {
+                Ok(data) if data.is_empty() => (),
+                Ok(data) => {
+                    let written = match chunked {
+                        true => self.write(&ChunkedEncoder::encode_chunk(&data)).await,
+                        false => self.write(&data).await,
+                    };
+                    if !written {
+                        return false;
+                    }
+                },
+                Err(reason) => {
+                    log::error!("Response stream failed! Closing connection without ending the body.\n\t{}", reason);
+                    return false;
+                }
+            }
+        }
+
+        !chunked || self.write(ChunkedEncoder::LAST_CHUNK).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> bool {
+        let write_timeout = self.config.timeouts.write;
+        match tokio::time::timeout(write_timeout, self.reader.get_mut().write_all(bytes)).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                log::error!("Failed to write to TcpStream in respond!\n\t{}", e);
+                false
+            },
+            Err(_) => {
+                log::warn!("Write did not finish within {} seconds, dropping connection", write_timeout.as_secs());
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::watch,
+    };
+
+    use super::{ConnectionConfig, HttpConnection};
+    use crate::{
+        http::{
+            limits::ServerTimeouts,
+            request::HttpRequestMethod,
+            response::HttpResponse,
+        },
+        router::HttpRouter,
+    };
+
+    #[tokio::test]
+    async fn closes_a_stalled_stream_without_ending_the_body() {
+        // kept alive past the handler, so the stream neither ends nor sends
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let mut router = HttpRouter::new();
+        let stalled = Arc::clone(&senders);
+        router.add_route(HttpRequestMethod::Get, "/stalled", move |http_request| {
+            let body_sender = http_request.respond_stream(HttpResponse::ok(), 1);
+            body_sender.blocking_send("first").unwrap();
+            stalled.lock().unwrap().push(body_sender);
+        });
+        let config = ConnectionConfig {
+            timeouts: ServerTimeouts {
+                write: Duration::from_millis(100),
+                ..ServerTimeouts::default()
+            },
+            ..ConnectionConfig::default()
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        tokio::spawn(HttpConnection::new(server, Arc::new(router), Arc::new(config), shutdown_rx).serve());
+
+        client.write_all(b"GET /stalled HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response)).await
+            .expect("the stalled stream should close the connection")
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n5\r\nfirst\r\n"), "{}", response);
+        assert_eq!(senders.lock().unwrap().len(), 1);
+    }
+}
@@ -1,249 +1,194 @@
-use std::collections::HashMap;
+/// Failure to percent-decode a URL component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// `%` not followed by two hex digits, at the byte offset of the `%`
+    InvalidEscape(usize),
+    /// The decoded bytes are not valid UTF-8
+    InvalidUtf8,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::InvalidEscape(position) => write!(f, "Invalid percent-encoding at byte {}", position),
+            DecodeError::InvalidUtf8 => write!(f, "Percent-decoded bytes are not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
 
+/// Percent-decoding and encoding of URL components, per RFC 3986 2.1
+///     Decoding works on bytes, so multi-byte UTF-8 sequences such as `%C3%A9` are kept whole, and hex digits may be either case.
 pub struct HttpUrlDecoder;
 
 impl HttpUrlDecoder {
-    pub fn decode_utf_8(line: &str) -> String {
-        let mut decoded = String::new();
-        let mut chars = line.chars();
-        while let Some(c) = chars.next() {
-            if c == '%' {
-                let mut encoded = String::new();
-                encoded.push('%');
-                if let Some(c) = chars.next() {
-                    encoded.push(c);
-                    if let Some(c) = chars.next() {
-                        encoded.push(c);
-                    }
-                }
-                match ENCODED_CHARACTERS.get(&encoded[..]) {
-                    Some(decoded_char) => decoded.push_str(decoded_char),
-                    None => decoded.push_str(&encoded[..]),
-                }
-            } else {
-                decoded.push(c);
+    /// Decodes a path segment or any other component where `+` is literal
+    pub fn decode_utf_8(component: &str) -> Result<String, DecodeError> {
+        let decoded = Self::decode_bytes(component.as_bytes(), false)?;
+        String::from_utf8(decoded).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    /// Decodes a component of a query string or url-encoded form, where `+` is a space
+    pub fn decode_form_component(component: &str) -> Result<String, DecodeError> {
+        let decoded = Self::decode_bytes(component.as_bytes(), true)?;
+        String::from_utf8(decoded).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    /// Decodes a component of a query string or url-encoded form without failing.
+    ///     Malformed escapes are kept as sent and invalid UTF-8 is replaced with U+FFFD, as browsers do for forms.
+    pub fn decode_form_bytes(raw: &[u8]) -> String {
+        let mut decoded: Vec<u8> = Vec::with_capacity(raw.len());
+        let mut index = 0;
+        while index < raw.len() {
+            match raw[index] {
+                b'+' => decoded.push(b' '),
+                b'%' => match Self::escaped_byte(raw, index) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    },
+                    None => decoded.push(b'%'),
+                },
+                byte => decoded.push(byte),
+            }
+            index += 1;
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
+    }
+
+    /// Percent-decodes raw bytes, failing on a malformed escape
+    ///
+    /// # Arguments
+    ///
+    /// * `plus_as_space` - Whether `+` decodes to a space, as in url-encoded forms
+    pub fn decode_bytes(raw: &[u8], plus_as_space: bool) -> Result<Vec<u8>, DecodeError> {
+        let mut decoded: Vec<u8> = Vec::with_capacity(raw.len());
+        let mut index = 0;
+        while index < raw.len() {
+            match raw[index] {
+                b'+' if plus_as_space => decoded.push(b' '),
+                b'%' => {
+                    decoded.push(Self::escaped_byte(raw, index).ok_or(DecodeError::InvalidEscape(index))?);
+                    index += 2;
+                },
+                byte => decoded.push(byte),
             }
+            index += 1;
         }
-        decoded
+        Ok(decoded)
+    }
+
+    /// Encodes every byte but the unreserved characters `A-Z a-z 0-9 - . _ ~`
+    pub fn encode_utf_8(component: &str) -> String {
+        Self::encode(component, Self::is_unreserved, false)
+    }
+
+    /// Encodes a single path segment, `/` is encoded so it can not split the segment
+    pub fn encode_path_segment(segment: &str) -> String {
+        // pchar from RFC 3986 3.3
+        Self::encode(segment, |byte| Self::is_unreserved(byte) || Self::is_sub_delim(byte) || byte == b':' || byte == b'@', false)
+    }
+
+    /// Encodes a key or value of a query string, `&`, `=` and `+` are encoded so they keep their meaning
+    pub fn encode_query_component(component: &str) -> String {
+        // query from RFC 3986 3.4, less the characters that delimit or decode differently in a query
+        Self::encode(component, |byte| Self::is_unreserved(byte) || b"!$'()*,;:@/?".contains(&byte), false)
+    }
+
+    /// Encodes a key or value of an application/x-www-form-urlencoded body, spaces become `+`
+    pub fn encode_form_component(component: &str) -> String {
+        // the urlencoded byte serializer of the WHATWG URL standard
+        Self::encode(component, |byte| byte.is_ascii_alphanumeric() || b"*-._".contains(&byte), true)
     }
 
-    pub fn encode_utf_8(line: &str) -> String {
-        let mut encoded = String::new();
-        while let Some(c) = line.chars().next() {
-            let mut encoded_char = String::new();
-            encoded_char.push(c);
-            match DECODED_CHARACTERS.get(&encoded_char[..]) {
-                Some(encoded_char) => encoded.push_str(encoded_char),
-                None => encoded.push(c),
+    fn encode(component: &str, keep: fn(u8) -> bool, space_as_plus: bool) -> String {
+        let mut encoded = String::with_capacity(component.len());
+        for byte in component.bytes() {
+            match byte {
+                b' ' if space_as_plus => encoded.push('+'),
+                byte if keep(byte) => encoded.push(byte as char),
+                byte => encoded.push_str(&format!("%{:02X}", byte)),
             }
         }
         encoded
     }
-}
 
-lazy_static! {
-    static ref ENCODED_CHARACTERS: HashMap<&'static str, &'static str> = {
-        let mut m = HashMap::new();
-        m.insert("%20", " ");
-        m.insert("%21", "!");
-        m.insert("%22", "\"");
-        m.insert("%23", "#");
-        m.insert("%24", "$");
-        m.insert("%25", "%");
-        m.insert("%26", "&");
-        m.insert("%27", "'");
-        m.insert("%28", "(");
-        m.insert("%29", ")");
-        m.insert("%2A", "*");
-        m.insert("%2B", "+");
-        m.insert("%2C", ",");
-        m.insert("%2D", "-");
-        m.insert("%2E", ".");
-        m.insert("%2F", "/");
-        m.insert("%30", "0");
-        m.insert("%31", "1");
-        m.insert("%32", "2");
-        m.insert("%33", "3");
-        m.insert("%34", "4");
-        m.insert("%35", "5");
-        m.insert("%36", "6");
-        m.insert("%37", "7");
-        m.insert("%38", "8");
-        m.insert("%39", "9");
-        m.insert("%3A", ":");
-        m.insert("%3B", ";");
-        m.insert("%3C", "<");
-        m.insert("%3D", "=");
-        m.insert("%3E", ">");
-        m.insert("%3F", "?");
-        m.insert("%40", "@");
-        m.insert("%41", "A");
-        m.insert("%42", "B");
-        m.insert("%43", "C");
-        m.insert("%44", "D");
-        m.insert("%45", "E");
-        m.insert("%46", "F");
-        m.insert("%47", "G");
-        m.insert("%48", "H");
-        m.insert("%49", "I");
-        m.insert("%4A", "J");
-        m.insert("%4B", "K");
-        m.insert("%4C", "L");
-        m.insert("%4D", "M");
-        m.insert("%4E", "N");
-        m.insert("%4F", "O");
-        m.insert("%50", "P");
-        m.insert("%51", "Q");
-        m.insert("%52", "R");
-        m.insert("%53", "S");
-        m.insert("%54", "T");
-        m.insert("%55", "U");
-        m.insert("%56", "V");
-        m.insert("%57", "W");
-        m.insert("%58", "X");
-        m.insert("%59", "Y");
-        m.insert("%5A", "Z");
-        m.insert("%5B", "[");
-        m.insert("%5C", "\\");
-        m.insert("%5D", "]");
-        m.insert("%5E", "^");
-        m.insert("%5F", "_");
-        m.insert("%60", "`");
-        m.insert("%61", "a");
-        m.insert("%62", "b");
-        m.insert("%63", "c");
-        m.insert("%64", "d");
-        m.insert("%65", "e");
-        m.insert("%66", "f");
-        m.insert("%67", "g");
-        m.insert("%68", "h");
-        m.insert("%69", "i");
-        m.insert("%6A", "j");
-        m.insert("%6B", "k");
-        m.insert("%6C", "l");
-        m.insert("%6D", "m");
-        m.insert("%6E", "n");
-        m.insert("%6F", "o");
-        m.insert("%70", "p");
-        m.insert("%71", "q");
-        m.insert("%72", "r");
-        m.insert("%73", "s");
-        m.insert("%74", "t");
-        m.insert("%75", "u");
-        m.insert("%76", "v");
-        m.insert("%77", "w");
-        m.insert("%78", "x");
-        m.insert("%79", "y");
-        m.insert("%7A", "z");
-        m.insert("%7B", "{");
-        m.insert("%7C", "|");
-        m.insert("%7D", "}");
-        m.insert("%7E", "~");
-        m.insert("%80", "€");
-        m.insert("%0A", "\n");
-        m
-    };
-    // reverse lookup
-    static ref DECODED_CHARACTERS: HashMap<&'static str, &'static str> = {
-        let mut m = HashMap::new();
-        m.insert(" ", "%20");
-        m.insert("!", "%21");
-        m.insert("\"", "%22");
-        m.insert("#", "%23");
-        m.insert("$", "%24");
-        m.insert("%", "%25");
-        m.insert("&", "%26");
-        m.insert("'", "%27");
-        m.insert("(", "%28");
-        m.insert(")", "%29");
-        m.insert("*", "%2A");
-        m.insert("+", "%2B");
-        m.insert(",", "%2C");
-        m.insert("-", "%2D");
-        m.insert(".", "%2E");
-        m.insert("/", "%2F");
-        m.insert("0", "%30");
-        m.insert("1", "%31");
-        m.insert("2", "%32");
-        m.insert("3", "%33");
-        m.insert("4", "%34");
-        m.insert("5", "%35");
-        m.insert("6", "%36");
-        m.insert("7", "%37");
-        m.insert("8", "%38");
-        m.insert("9", "%39");
-        m.insert(":", "%3A");
-        m.insert(";", "%3B");
-        m.insert("<", "%3C");
-        m.insert("=", "%3D");
-        m.insert(">", "%3E");
-        m.insert("?", "%3F");
-        m.insert("@", "%40");
-        m.insert("A", "%41");
-        m.insert("B", "%42");
-        m.insert("C", "%43");
-        m.insert("D", "%44");
-        m.insert("E", "%45");
-        m.insert("F", "%46");
-        m.insert("G", "%47");
-        m.insert("H", "%48");
-        m.insert("I", "%49");
-        m.insert("J", "%4A");
-        m.insert("K", "%4B");
-        m.insert("L", "%4C");
-        m.insert("M", "%4D");
-        m.insert("N", "%4E");
-        m.insert("O", "%4F");
-        m.insert("P", "%50");
-        m.insert("Q", "%51");
-        m.insert("R", "%52");
-        m.insert("S", "%53");
-        m.insert("T", "%54");
-        m.insert("U", "%55");
-        m.insert("V", "%56");
-        m.insert("W", "%57");
-        m.insert("X", "%58");
-        m.insert("Y", "%59");
-        m.insert("Z", "%5A");
-        m.insert("[", "%5B");
-        m.insert("\\", "%5C");
-        m.insert("]", "%5D");
-        m.insert("^", "%5E");
-        m.insert("_", "%5F");
-        m.insert("`", "%60");
-        m.insert("a", "%61");
-        m.insert("b", "%62");
-        m.insert("c", "%63");
-        m.insert("d", "%64");
-        m.insert("e", "%65");
-        m.insert("f", "%66");
-        m.insert("g", "%67");
-        m.insert("h", "%68");
-        m.insert("i", "%69");
-        m.insert("j", "%6A");
-        m.insert("k", "%6B");
-        m.insert("l", "%6C");
-        m.insert("m", "%6D");
-        m.insert("n", "%6E");
-        m.insert("o", "%6F");
-        m.insert("p", "%70");
-        m.insert("q", "%71");
-        m.insert("r", "%72");
-        m.insert("s", "%73");
-        m.insert("t", "%74");
-        m.insert("u", "%75");
-        m.insert("v", "%76");
-        m.insert("w", "%77");
-        m.insert("x", "%78");
-        m.insert("y", "%79");
-        m.insert("z", "%7A");
-        m.insert("{", "%7B");
-        m.insert("|", "%7C");
-        m.insert("}", "%7D");
-        m.insert("~", "%7E");
-        m.insert("€", "%80");
-        m.insert("\n", "%0A");
-        m
-    };
+    /// unreserved from RFC 3986 2.3
+    fn is_unreserved(byte: u8) -> bool {
+        byte.is_ascii_alphanumeric() || b"-._~".contains(&byte)
+    }
+
+    /// sub-delims from RFC 3986 2.2
+    fn is_sub_delim(byte: u8) -> bool {
+        b"!$&'()*+,;=".contains(&byte)
+    }
+
+    /// Byte of the escape starting at index, None if it is not followed by two hex digits
+    fn escaped_byte(raw: &[u8], index: usize) -> Option<u8> {
+        let high = (*raw.get(index + 1)? as char).to_digit(16)?;
+        let low = (*raw.get(index + 2)? as char).to_digit(16)?;
+        Some(((high << 4) | low) as u8)
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{DecodeError, HttpUrlDecoder};
+
+    #[test]
+    fn decodes_multi_byte_utf_8() {
+        assert_eq!(HttpUrlDecoder::decode_utf_8("caf%C3%A9").as_deref(), Ok("café"));
+        assert_eq!(HttpUrlDecoder::decode_form_component("%E2%9C%93+ok").as_deref(), Ok("✓ ok"));
+        assert_eq!(HttpUrlDecoder::decode_form_bytes(b"%F0%9F%A6%80"), "🦀");
+    }
+
+    #[test]
+    fn decodes_either_case_of_hex() {
+        assert_eq!(HttpUrlDecoder::decode_utf_8("a%2fb%2Fc").as_deref(), Ok("a/b/c"));
+        assert_eq!(HttpUrlDecoder::decode_utf_8("%c3%a9").as_deref(), Ok("é"));
+    }
+
+    #[test]
+    fn handles_invalid_utf_8() {
+        assert_eq!(HttpUrlDecoder::decode_utf_8("%80"), Err(DecodeError::InvalidUtf8));
+        assert_eq!(HttpUrlDecoder::decode_form_component("a%C3"), Err(DecodeError::InvalidUtf8));
+        assert_eq!(HttpUrlDecoder::decode_form_bytes(b"a%80b"), "a\u{FFFD}b");
+    }
+
+    #[test]
+    fn handles_malformed_escapes() {
+        assert_eq!(HttpUrlDecoder::decode_utf_8("ab%A"), Err(DecodeError::InvalidEscape(2)));
+        assert_eq!(HttpUrlDecoder::decode_utf_8("%G1"), Err(DecodeError::InvalidEscape(0)));
+        assert_eq!(HttpUrlDecoder::decode_utf_8("100%"), Err(DecodeError::InvalidEscape(3)));
+        // the lossy form decoder keeps them as sent
+        assert_eq!(HttpUrlDecoder::decode_form_bytes(b"ab%A"), "ab%A");
+        assert_eq!(HttpUrlDecoder::decode_form_bytes(b"%G1=100%"), "%G1=100%");
+    }
+
+    #[test]
+    fn decodes_plus_as_space_only_in_forms() {
+        assert_eq!(HttpUrlDecoder::decode_utf_8("a+b").as_deref(), Ok("a+b"));
+        assert_eq!(HttpUrlDecoder::decode_form_component("a+b").as_deref(), Ok("a b"));
+        assert_eq!(HttpUrlDecoder::decode_form_bytes(b"a+b"), "a b");
+        assert_eq!(HttpUrlDecoder::decode_form_component("a%2Bb").as_deref(), Ok("a+b"));
+    }
+
+    #[test]
+    fn encodes_components() {
+        assert_eq!(HttpUrlDecoder::encode_utf_8("a b/é~"), "a%20b%2F%C3%A9~");
+        assert_eq!(HttpUrlDecoder::encode_path_segment("a b/c:d@e+f"), "a%20b%2Fc:d@e+f");
+        assert_eq!(HttpUrlDecoder::encode_query_component("a b&c=d+e/f?"), "a%20b%26c%3Dd%2Be/f?");
+        assert_eq!(HttpUrlDecoder::encode_form_component("a b&c~"), "a+b%26c%7E");
+    }
+
+    #[test]
+    fn round_trips_through_encoding() {
+        for text in ["plain", "a b", "a+b", "a/b?c#d", "x&y=z", "100%", "café ✓ 🦀", "%41", "~-._!$'()*,;:@"] {
+            assert_eq!(HttpUrlDecoder::decode_utf_8(&HttpUrlDecoder::encode_path_segment(text)).as_deref(), Ok(text));
+            assert_eq!(HttpUrlDecoder::decode_utf_8(&HttpUrlDecoder::encode_utf_8(text)).as_deref(), Ok(text));
+            assert_eq!(HttpUrlDecoder::decode_form_component(&HttpUrlDecoder::encode_query_component(text)).as_deref(), Ok(text));
+            assert_eq!(HttpUrlDecoder::decode_form_bytes(HttpUrlDecoder::encode_form_component(text).as_bytes()), text);
+        }
+    }
+}
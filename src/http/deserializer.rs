@@ -0,0 +1,387 @@
+use serde::de::{
+    self,
+    value::StrDeserializer,
+    DeserializeOwned, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+
+/// Failure to deserialize path parameters, a query string or form fields into a type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeserializeError {
+    pub message: String,
+}
+
+impl std::fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+impl de::Error for DeserializeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        DeserializeError {
+            message: msg.to_string(),
+        }
+    }
+}
+
+/// Deserializes string key/value pairs, such as FormData or PathParams, into T.
+///     Values are parsed into whatever type T asks for, so `id=42` fills a u64 field.
+///     Repeated keys and `key[]` fill sequences, e.g. `tag=a&tag=b` or `ids[]=1&ids[]=2` for a Vec field.
+///     A T that is a tuple or sequence takes the values in order, and any other single value takes the only pair's value.
+pub fn from_pairs<'a, T, I>(pairs: I) -> Result<T, DeserializeError>
+where
+    T: DeserializeOwned,
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut grouped: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut ordered: Vec<&str> = Vec::new();
+    for (key, value) in pairs {
+        let key = key.strip_suffix("[]").unwrap_or(key);
+        match grouped.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, values)) => values.push(value),
+            None => grouped.push((key, vec![value])),
+        }
+        ordered.push(value);
+    }
+    T::deserialize(PairsDeserializer { grouped, ordered })
+}
+
+struct PairsDeserializer<'a> {
+    grouped: Vec<(&'a str, Vec<&'a str>)>,
+    ordered: Vec<&'a str>,
+}
+
+impl<'a> PairsDeserializer<'a> {
+    fn single(self) -> Result<ValueDeserializer<'a>, DeserializeError> {
+        match self.ordered.as_slice() {
+            [value] => Ok(ValueDeserializer(value)),
+            values => Err(de::Error::custom(format!("expected a single value, found {}", values.len()))),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for PairsDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(PairsAccess { pairs: self.grouped.into_iter(), values: None })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(ValuesAccess(self.ordered.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.ordered.is_empty() {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_identifier deserialize_ignored_any
+    }
+}
+
+struct PairsAccess<'a> {
+    pairs: std::vec::IntoIter<(&'a str, Vec<&'a str>)>,
+    values: Option<Vec<&'a str>>,
+}
+
+impl<'de, 'a> MapAccess<'de> for PairsAccess<'a> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        match self.pairs.next() {
+            Some((key, values)) => {
+                self.values = Some(values);
+                let key: StrDeserializer<DeserializeError> = key.into_deserializer();
+                seed.deserialize(key).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let values = self.values.take().unwrap_or_default();
+        seed.deserialize(ValuesDeserializer(values))
+    }
+}
+
+struct ValuesAccess<I>(I);
+
+impl<'de, 'a, I> SeqAccess<'de> for ValuesAccess<I>
+where
+    I: Iterator<Item = &'a str>,
+{
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        match self.0.next() {
+            Some(value) => seed.deserialize(ValueDeserializer(value)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Every value sent for one key, a sequence if asked for one and otherwise the last value
+struct ValuesDeserializer<'a>(Vec<&'a str>);
+
+impl<'a> ValuesDeserializer<'a> {
+    fn last(self) -> ValueDeserializer<'a> {
+        ValueDeserializer(self.0.last().copied().unwrap_or_default())
+    }
+}
+
+macro_rules! forward_to_last {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.last().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for ValuesDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(ValuesAccess(self.0.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        self.last().deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        self.last().deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        self.last().deserialize_unit_struct(name, visitor)
+    }
+
+    forward_to_last! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_unit deserialize_map
+        deserialize_identifier deserialize_ignored_any
+    }
+}
+
+/// A single value, parsed into whatever type is asked for
+struct ValueDeserializer<'a>(&'a str);
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(e) => Err(de::Error::custom(format!("invalid value \"{}\": {}", self.0, e))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_str(self.0)
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(ValuesAccess(std::iter::once(self.0)))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Self::Error> {
+        let variant: StrDeserializer<DeserializeError> = self.0.into_deserializer();
+        variant.deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::from_pairs;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Person {
+        id: u64,
+        age: u8,
+        score: f64,
+        admin: bool,
+        name: String,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Sort {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Search {
+        #[serde(default)]
+        tag: Vec<String>,
+        #[serde(default)]
+        ids: Vec<u32>,
+        page: Option<u32>,
+        sort: Option<Sort>,
+    }
+
+    #[test]
+    fn fills_struct_fields_by_name() {
+        let person: Person = from_pairs([("name", "John"), ("id", "42"), ("age", "22"), ("score", "-1.5"), ("admin", "true")]).unwrap();
+        assert_eq!(person, Person { id: 42, age: 22, score: -1.5, admin: true, name: "John".to_string() });
+    }
+
+    #[test]
+    fn fills_tuples_in_order() {
+        let (id, name): (u32, String) = from_pairs([("person_id", "7"), ("name", "bob")]).unwrap();
+        assert_eq!((id, name.as_str()), (7, "bob"));
+    }
+
+    #[test]
+    fn fills_a_single_value_from_the_only_pair() {
+        assert_eq!(from_pairs::<u64, _>([("id", "42")]), Ok(42));
+        assert_eq!(from_pairs::<String, _>([("name", "bob")]), Ok("bob".to_string()));
+        assert_eq!(from_pairs::<Sort, _>([("sort", "desc")]), Ok(Sort::Desc));
+        assert_eq!(from_pairs::<u64, _>([("a", "1"), ("b", "2")]).unwrap_err().message, "expected a single value, found 2");
+        assert_eq!(from_pairs::<u64, _>([]).unwrap_err().message, "expected a single value, found 0");
+    }
+
+    #[test]
+    fn fills_sequences_from_repeated_and_bracketed_keys() {
+        let search: Search = from_pairs([("tag", "a"), ("ids[]", "1"), ("tag", "b"), ("ids[]", "2")]).unwrap();
+        assert_eq!(search.tag, vec!["a", "b"]);
+        assert_eq!(search.ids, vec![1, 2]);
+        // a single value is a sequence of one
+        let search: Search = from_pairs([("tag", "a")]).unwrap();
+        assert_eq!(search.tag, vec!["a"]);
+    }
+
+    #[test]
+    fn keeps_the_last_value_of_a_repeated_key() {
+        let search: Search = from_pairs([("page", "1"), ("page", "3")]).unwrap();
+        assert_eq!(search.page, Some(3));
+    }
+
+    #[test]
+    fn leaves_missing_options_as_none() {
+        let search: Search = from_pairs([]).unwrap();
+        assert_eq!(search, Search { tag: Vec::new(), ids: Vec::new(), page: None, sort: None });
+        let search: Search = from_pairs([("sort", "asc"), ("page", "2")]).unwrap();
+        assert_eq!((search.page, search.sort), (Some(2), Some(Sort::Asc)));
+        // a value of its own is None only if nothing was sent
+        assert_eq!(from_pairs::<Option<u64>, _>([]), Ok(None));
+        assert_eq!(from_pairs::<Option<u64>, _>([("id", "5")]), Ok(Some(5)));
+    }
+
+    #[test]
+    fn rejects_values_that_do_not_parse() {
+        let error = from_pairs::<Person, _>([("name", "John"), ("id", "abc"), ("age", "22"), ("score", "1"), ("admin", "true")]).unwrap_err();
+        assert!(error.message.starts_with("invalid value \"abc\": "), "{}", error);
+        assert!(from_pairs::<Search, _>([("page", "-1")]).is_err());
+        assert!(from_pairs::<Search, _>([("sort", "sideways")]).is_err());
+        assert!(from_pairs::<Person, _>([("id", "1")]).unwrap_err().message.contains("missing field"));
+    }
+}
@@ -0,0 +1,161 @@
+use super::decoder::HttpUrlDecoder;
+
+/// Decoded key/value pairs of a query string or url-encoded form, e.g. `?tag=a&tag=b&name=John+Doe`
+///     Repeated keys are kept in the order they were sent.
+///     Keys are kept as sent, so bracket notation such as `user[name]` or `ids[]` can be read with group and get_list.
+#[derive(Clone, Default, Debug)]
+pub struct FormData {
+    pairs: Vec<(String, String)>,
+}
+
+impl FormData {
+    pub fn new() -> FormData {
+        FormData {
+            pairs: Vec::new(),
+        }
+    }
+
+    /// Parses a raw query string, without the leading `?`, or a url-encoded body
+    ///     `+` is treated as a space and keys without `=` get an empty value.
+    ///     Values are decoded as bytes, so multi-byte UTF-8 survives, invalid UTF-8 is replaced with U+FFFD.
+    pub fn parse(raw: &[u8]) -> FormData {
+        let pairs = raw.split(|b| *b == b'&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = match pair.iter().position(|b| *b == b'=') {
+                    Some(index) => (&pair[..index], &pair[index + 1..]),
+                    None => (pair, &pair[pair.len()..]),
+                };
+                (HttpUrlDecoder::decode_form_bytes(key), HttpUrlDecoder::decode_form_bytes(value))
+            })
+            .collect();
+        FormData {
+            pairs,
+        }
+    }
+
+    /// Adds a pair, keeping any sent before it
+    pub fn append(&mut self, key: &str, value: &str) {
+        self.pairs.push((key.to_string(), value.to_string()));
+    }
+
+    /// First value sent for the key
+    ///     Returned as a String reference like the map it replaced, so `get(key).cloned()` still owns the value.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.pairs.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Every value sent for the key, in order
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs.iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Every value sent for the key as a list, with or without brackets, e.g. `ids[]=1&ids[]=2` or `ids=1&ids=2`
+    pub fn get_list(&self, key: &str) -> Vec<&str> {
+        self.pairs.iter()
+            .filter(|(k, _)| k.strip_prefix(key).is_some_and(|rest| rest.is_empty() || rest == "[]"))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Nested fields of the key, with the outer brackets removed
+    ///     e.g. `user[name]=x&user[address][city]=y` grouped by `user` has `name` and `address[city]`.
+    pub fn group(&self, key: &str) -> FormData {
+        let pairs = self.pairs.iter()
+            .filter_map(|(k, value)| {
+                let nested = k.strip_prefix(key)?.strip_prefix('[')?;
+                let close = nested.find(']')?;
+                if close == 0 {
+                    // `key[]` is a list item, not a nested field
+                    return None;
+                }
+                Some((format!("{}{}", &nested[..close], &nested[close + 1..]), value.clone()))
+            })
+            .collect();
+        FormData {
+            pairs,
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FormData;
+    use crate::http::{headers::HttpHeaders, limits::ServerLimits, shared::HttpHeaderBody, status::StatusCode};
+
+    #[test]
+    fn keeps_repeated_keys_in_order() {
+        let form = FormData::parse(b"tag=b&name=John&tag=a&tag=c");
+        assert_eq!(form.get_all("tag"), vec!["b", "a", "c"]);
+        assert_eq!(form.get("tag").map(String::as_str), Some("b"));
+        assert_eq!(form.get_all("missing"), Vec::<&str>::new());
+        assert_eq!(form.len(), 4);
+    }
+
+    #[test]
+    fn lists_keys_with_and_without_brackets() {
+        let form = FormData::parse(b"ids=1&ids[]=2&idsx=3&ids[0]=4&ids%5B%5D=5");
+        assert_eq!(form.get_list("ids"), vec!["1", "2", "5"]);
+        assert_eq!(form.get_all("ids"), vec!["1"]);
+    }
+
+    #[test]
+    fn groups_nested_fields() {
+        let form = FormData::parse(b"user[name]=John&user[address][city]=Boston&user[]=x&username=no&a[b][c]=deep");
+        let user = form.group("user");
+        assert_eq!(user.get("name").map(String::as_str), Some("John"));
+        assert_eq!(user.get("address[city]").map(String::as_str), Some("Boston"));
+        assert_eq!(user.group("address").get("city").map(String::as_str), Some("Boston"));
+        assert_eq!(user.len(), 2);
+        assert_eq!(form.group("a").group("b").get("c").map(String::as_str), Some("deep"));
+        assert!(form.group("missing").is_empty());
+    }
+
+    #[test]
+    fn parses_empty_values_and_bare_keys() {
+        let form = FormData::parse(b"empty=&bare&&=novalue&name=John+Doe&eq=a=b");
+        assert_eq!(form.get("empty").map(String::as_str), Some(""));
+        assert_eq!(form.get("bare").map(String::as_str), Some(""));
+        assert!(form.contains("bare"));
+        assert_eq!(form.get("").map(String::as_str), Some("novalue"));
+        assert_eq!(form.get("name").map(String::as_str), Some("John Doe"));
+        assert_eq!(form.get("eq").map(String::as_str), Some("a=b"));
+        assert_eq!(form.len(), 5);
+        assert!(FormData::parse(b"").is_empty());
+    }
+
+    #[test]
+    fn rejects_forms_over_the_field_limit() {
+        let limits = ServerLimits {
+            max_form_fields: 2,
+            ..ServerLimits::default()
+        };
+        let mut headers = HttpHeaders::new();
+        headers.append("Content-Type", "application/x-www-form-urlencoded");
+        let body = HttpHeaderBody::new(Vec::new(), &headers, b"a=1&b=2".to_vec(), Vec::new(), &limits);
+        assert_eq!(body.ok().and_then(|body| body.body_params).map(|form| form.len()), Some(2));
+        let body = HttpHeaderBody::new(Vec::new(), &headers, b"a=1&b=2&c=3".to_vec(), Vec::new(), &limits);
+        assert_eq!(body.err().map(|failure| failure.status), Some(StatusCode::CONTENT_TOO_LARGE));
+    }
+}
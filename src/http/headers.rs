@@ -0,0 +1,70 @@
+/// Ordered multimap of HTTP header fields.
+///     Field names keep the casing they were added with, but every lookup is case-insensitive.
+#[derive(Clone, Default, Debug)]
+pub struct HttpHeaders {
+    fields: Vec<(String, String)>,
+}
+
+impl HttpHeaders {
+    pub fn new() -> HttpHeaders {
+        HttpHeaders {
+            fields: Vec::new(),
+        }
+    }
+
+    /// First value of the field, if present
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the field, in the order received
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.fields.iter()
+            .filter(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Every comma separated element of the field's values, trimmed, e.g. `Connection: close, TE`
+    pub fn get_list(&self, name: &str) -> Vec<&str> {
+        self.get_all(name).into_iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| element.trim())
+            .filter(|element| !element.is_empty())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields.iter().any(|(field, _)| field.eq_ignore_ascii_case(name))
+    }
+
+    /// Sets the field to a single value, replacing any values already present
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Adds a value to the field, keeping any values already present
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    /// Removes every value of the field
+    pub fn remove(&mut self, name: &str) {
+        self.fields.retain(|(field, _)| !field.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(field, value)| (field.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
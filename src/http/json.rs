@@ -0,0 +1,79 @@
+use super::{response::{HttpResponse, IntoResponse}, status::StatusCode};
+
+/// Failure to deserialize a JSON request body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub message: String,
+    /// 1 based line of the error, 0 if the error is not tied to a position
+    pub line: usize,
+    /// 1 based column of the error, 0 if the error is not tied to a position
+    pub column: usize,
+}
+
+impl JsonError {
+    /// 400 Bad Request describing where the body failed to parse, e.g.
+    ///     `{"error": "expected `,` or `}`", "line": 3, "column": 5}`
+    pub fn to_response(&self) -> HttpResponse {
+        let body = serde_json::json!({
+            "error": self.message,
+            "line": self.line,
+            "column": self.column,
+        });
+        HttpResponse::new(StatusCode::BAD_REQUEST).with_body(body.to_string())
+    }
+}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(error: serde_json::Error) -> Self {
+        let message = error.to_string();
+        // serde_json appends " at line x column y", which the fields already carry
+        let message = match message.rfind(" at line ") {
+            Some(index) if error.line() > 0 => message[..index].to_string(),
+            _ => message,
+        };
+        JsonError {
+            message,
+            line: error.line(),
+            column: error.column(),
+        }
+    }
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at line {} column {}", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> HttpResponse {
+        self.to_response()
+    }
+}
+
+/// Value answered as an application/json body, e.g. `Json(person)` returned from a handler
+#[derive(Debug, Clone)]
+pub struct Json<T>(pub T);
+
+/// 200 OK with the value serialized as the body, or 500 if it fails to serialize
+impl<T: serde::Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> HttpResponse {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => HttpResponse::ok()
+                .with_header("Content-Type", "application/json")
+                .with_body(body),
+            Err(e) => {
+                log::error!("Failed to serialize JSON response!\n\t{}", e);
+                HttpResponse::error()
+            }
+        }
+    }
+}
+
+impl IntoResponse for serde_json::Value {
+    fn into_response(self) -> HttpResponse {
+        Json(self).into_response()
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpStream,
+};
+
+/// Caps on what a single request may make the server buffer.
+///     Requests over a limit are answered with 414, 431 or 413 and the connection is closed.
+#[derive(Clone, Debug)]
+pub struct ServerLimits {
+    /// Max bytes of the request line, e.g. `GET /person?id=3 HTTP/1.1` (414 URI Too Long)
+    pub max_request_line: usize,
+    /// Max bytes of all header lines together, trailers included (431 Request Header Fields Too Large)
+    pub max_header_bytes: usize,
+    /// Max number of header fields, trailers included (431 Request Header Fields Too Large)
+    pub max_headers: usize,
+    /// Max bytes of body, after chunked decoding (413 Content Too Large)
+    pub max_body: usize,
+    /// Max fields in a url-encoded or multipart form body (413 Content Too Large)
+    pub max_form_fields: usize,
+    /// Max bytes of a single multipart part (413 Content Too Large)
+    pub max_part_size: usize,
+    /// Multipart parts over this many bytes are written to a temp file instead of kept in memory
+    pub part_memory_threshold: usize,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        ServerLimits {
+            max_request_line: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+            max_body: 10 * 1024 * 1024,
+            max_form_fields: 1000,
+            max_part_size: 10 * 1024 * 1024,
+            part_memory_threshold: 256 * 1024,
+        }
+    }
+}
+
+/// Deadlines for each stage of a request.
+///     Every expiry is logged, reads are answered with 408 and handlers with 503.
+#[derive(Clone, Debug)]
+pub struct ServerTimeouts {
+    /// Max time from the first byte of a request until its header is received, and for a new connection to send that byte (408 Request Timeout)
+    pub header_read: Duration,
+    /// Max time to receive the whole body once the header is received (408 Request Timeout)
+    pub body_read: Duration,
+    /// Max time a handler may run before the request is answered for it (503 Service Unavailable)
+    pub handler: Duration,
+    /// Max time for each write of the response, and to wait on each chunk of a streamed body, before the connection is dropped
+    pub write: Duration,
+}
+
+impl Default for ServerTimeouts {
+    fn default() -> Self {
+        ServerTimeouts {
+            header_read: Duration::from_secs(10),
+            body_read: Duration::from_secs(30),
+            handler: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LineReadError {
+    /// The line is longer than allowed, the rest of it is left unread
+    TooLong,
+    /// The connection closed partway through the line, before its line ending
+    UnexpectedEof,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for LineReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LineReadError::TooLong => write!(f, "Line exceeds the allowed length"),
+            LineReadError::UnexpectedEof => write!(f, "Connection closed before the line ended"),
+            LineReadError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+pub struct LimitedLineReader;
+
+impl LimitedLineReader {
+    /// Reads a line of at most max_len bytes, not counting its line ending, which is removed.
+    ///     Returns None if the connection closed before any of the line was read, and fails if it closed partway through.
+    pub async fn read_line(buf_reader: &mut BufReader<TcpStream>, max_len: usize) -> Result<Option<String>, LineReadError> {
+        let mut line: Vec<u8> = Vec::new();
+        loop {
+            let buf = buf_reader.fill_buf().await.map_err(LineReadError::Io)?;
+            if buf.is_empty() {
+                return match line.is_empty() {
+                    true => Ok(None),
+                    false => Err(LineReadError::UnexpectedEof),
+                };
+            }
+
+            let (taken, done) = match buf.iter().position(|b| *b == b'\n') {
+                Some(index) => (index + 1, true),
+                None => (buf.len(), false),
+            };
+            line.extend_from_slice(&buf[..taken]);
+            buf_reader.consume(taken);
+
+            // room is left for the CRLF, which is not counted
+            if line.len() > max_len.saturating_add(2) {
+                return Err(LineReadError::TooLong);
+            }
+            if done {
+                break;
+            }
+        }
+
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        if line.len() > max_len {
+            return Err(LineReadError::TooLong);
+        }
+        match String::from_utf8(line) {
+            Ok(line) => Ok(Some(line)),
+            Err(e) => Err(LineReadError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))),
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::LineOrError;
+
+use super::{
+    headers::HttpHeaders,
+    limits::ServerLimits,
+    request::{HttpRequestFailure, HttpRequestParser},
+    shared::HttpHeaderBody,
+    status::StatusCode,
+};
+
+/// Longest boundary allowed, per RFC 2046 5.1.1
+const MAX_BOUNDARY_LEN: usize = 70;
+/// Whitespace allowed after a boundary before its CRLF, a generous cap on transport padding
+const MAX_BOUNDARY_PADDING: usize = 256;
+
+/// Field of a multipart/form-data body, per RFC 7578
+#[derive(Debug)]
+pub struct Part {
+    /// Field name from the Content-Disposition header
+    pub name: String,
+    /// File name from the Content-Disposition header, as sent by the client
+    pub filename: Option<String>,
+    /// Every header of the part, Content-Disposition included
+    pub headers: HttpHeaders,
+    /// Content-Type header of the part, as sent
+    pub content_type: Option<String>,
+    data: PartData,
+}
+
+#[derive(Debug)]
+enum PartData {
+    Memory(Vec<u8>),
+    /// Parts over the memory threshold are written to a temp file, removed once the part is dropped
+    File { file: TempFile, len: usize },
+}
+
+impl Part {
+    /// Size of the part's content in bytes
+    pub fn len(&self) -> usize {
+        match &self.data {
+            PartData::Memory(bytes) => bytes.len(),
+            PartData::File { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the part is a file upload, i.e. it was sent with a filename
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// Content of a part kept in memory, None if it was written to a temp file
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.data {
+            PartData::Memory(bytes) => Some(bytes),
+            PartData::File { .. } => None,
+        }
+    }
+
+    /// Location of the temp file holding the part, None if it is kept in memory
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            PartData::Memory(_) => None,
+            PartData::File { file, .. } => Some(&file.path),
+        }
+    }
+
+    /// Content of the part, read from its temp file if it has one
+    pub fn read_bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(bytes.clone()),
+            PartData::File { file, .. } => std::fs::read(&file.path),
+        }
+    }
+
+    /// Content of the part as UTF-8 text, fails with InvalidData if it is not valid UTF-8
+    pub fn text(&self) -> io::Result<String> {
+        String::from_utf8(self.read_bytes()?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Copies the content of the part to path, e.g. to keep an upload after the request is done
+    ///     The copy gets the default permissions of a new file, not the private ones of the temp file.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        match &self.data {
+            PartData::Memory(bytes) => std::fs::write(path, bytes),
+            PartData::File { file, .. } => {
+                let mut temp_file = std::fs::File::open(&file.path)?;
+                io::copy(&mut temp_file, &mut std::fs::File::create(path)?).map(|_| ())
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    async fn create() -> io::Result<(TempFile, File)> {
+        static CREATED: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.subsec_nanos()).unwrap_or_default();
+        let name = format!("m_server-part-{}-{}-{}", std::process::id(), nanos, CREATED.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        // uploads may be private, so other users of a shared temp dir must not read them
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options.open(&path).await?;
+        Ok((TempFile { path }, file))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::error!("Failed to remove temp file {}!\n\t{}", self.path.display(), e);
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum ParseState {
+    Preamble,
+    Boundary,
+    Headers,
+    Body,
+    Epilogue,
+}
+
+/// Incremental multipart/form-data parser, fed the body as it arrives.
+///     Content is never decoded as text, so binary parts are kept byte for byte.
+pub struct MultipartParser {
+    /// CRLF, then `--` and the boundary
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: ParseState,
+    received: bool,
+    parts: Vec<Part>,
+    current: Option<(Part, Option<File>)>,
+    max_parts: usize,
+    max_part_size: usize,
+    max_part_headers: usize,
+    memory_threshold: usize,
+}
+
+impl MultipartParser {
+    /// Parser for the request if its Content-Type is multipart/form-data, fails with 400 if it has no valid boundary
+    pub fn for_request(headers: &HttpHeaders, limits: &ServerLimits) -> Result<Option<MultipartParser>, HttpRequestFailure> {
+        let (media_type, params) = match headers.get("Content-Type") {
+            Some(content_type) => HttpHeaderBody::parse_content_type(content_type),
+            None => return Ok(None),
+        };
+        if media_type != "multipart/form-data" {
+            return Ok(None);
+        }
+        let boundary = params.into_iter()
+            .find(|(name, _)| name == "boundary")
+            .map(|(_, value)| value)
+            .unwrap_or_default();
+        if boundary.is_empty() || boundary.len() > MAX_BOUNDARY_LEN {
+            return Err(format!("Bad request! Invalid multipart boundary: {}", boundary).into());
+        }
+        Ok(Some(MultipartParser::new(&boundary, limits)))
+    }
+
+    pub fn new(boundary: &str, limits: &ServerLimits) -> MultipartParser {
+        MultipartParser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // the first boundary may open the body, without a CRLF before it
+            buffer: b"\r\n".to_vec(),
+            state: ParseState::Preamble,
+            received: false,
+            parts: Vec::new(),
+            current: None,
+            max_parts: limits.max_form_fields,
+            max_part_size: limits.max_part_size,
+            max_part_headers: limits.max_header_bytes,
+            memory_threshold: limits.part_memory_threshold,
+        }
+    }
+
+    /// Parses the next bytes of the body
+    ///     Fails with 413 for too many or too large parts, 431 for too large part headers, 400 for a malformed body.
+    pub async fn push(&mut self, data: &[u8]) -> Result<(), HttpRequestFailure> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.received = true;
+        self.buffer.extend_from_slice(data);
+        while self.advance().await? {}
+        Ok(())
+    }
+
+    /// Ends the body, failing with 400 if it ended before the closing boundary
+    pub fn finish(self) -> Result<Vec<Part>, HttpRequestFailure> {
+        match self.state {
+            ParseState::Epilogue => Ok(self.parts),
+            _ if !self.received => Ok(Vec::new()),
+            _ => Err("Bad request! Multipart body ended before its closing boundary".to_string().into()),
+        }
+    }
+
+    /// Parses as much of the buffer as the current state can, returns false once more bytes are needed
+    async fn advance(&mut self) -> Result<bool, HttpRequestFailure> {
+        match self.state {
+            ParseState::Preamble => {
+                match Self::find(&self.buffer, &self.delimiter) {
+                    Some(index) => {
+                        self.buffer.drain(..index + self.delimiter.len());
+                        self.state = ParseState::Boundary;
+                        Ok(true)
+                    },
+                    None => {
+                        // the preamble is ignored, only what could start the delimiter is kept
+                        let keep = self.delimiter.len() - 1;
+                        if self.buffer.len() > keep {
+                            self.buffer.drain(..self.buffer.len() - keep);
+                        }
+                        Ok(false)
+                    }
+                }
+            },
+            ParseState::Boundary => {
+                if self.buffer.len() < 2 {
+                    return Ok(false);
+                }
+                if self.buffer.starts_with(b"--") {
+                    self.buffer.clear();
+                    self.state = ParseState::Epilogue;
+                    return Ok(false);
+                }
+                let line_end = match Self::find(&self.buffer, b"\r\n") {
+                    Some(line_end) => line_end,
+                    None if self.buffer.len() > MAX_BOUNDARY_PADDING => {
+                        return Err("Bad request! Multipart boundary is not followed by CRLF".to_string().into());
+                    },
+                    None => return Ok(false),
+                };
+                if !self.buffer[..line_end].iter().all(|b| *b == b' ' || *b == b'\t') {
+                    return Err("Bad request! Multipart boundary is not followed by CRLF".to_string().into());
+                }
+                self.buffer.drain(..line_end + 2);
+                self.state = ParseState::Headers;
+                Ok(true)
+            },
+            ParseState::Headers => {
+                let header_end = match self.buffer.starts_with(b"\r\n") {
+                    true => Some(0),
+                    false => Self::find(&self.buffer, b"\r\n\r\n").map(|index| index + 2),
+                };
+                let header_end = match header_end {
+                    Some(header_end) => header_end,
+                    None if self.buffer.len() > self.max_part_headers => {
+                        return Err(HttpRequestFailure::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, format!("Multipart headers are over {} bytes", self.max_part_headers)));
+                    },
+                    None => return Ok(false),
+                };
+                if header_end > self.max_part_headers {
+                    return Err(HttpRequestFailure::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, format!("Multipart headers are over {} bytes", self.max_part_headers)));
+                }
+                if self.parts.len() >= self.max_parts {
+                    return Err(HttpRequestFailure::new(StatusCode::CONTENT_TOO_LARGE, format!("Form has over {} fields", self.max_parts)));
+                }
+                let part = Self::parse_part_headers(&self.buffer[..header_end])?;
+                self.buffer.drain(..header_end + 2);
+                self.current = Some((part, None));
+                self.state = ParseState::Body;
+                Ok(true)
+            },
+            ParseState::Body => {
+                match Self::find(&self.buffer, &self.delimiter) {
+                    Some(index) => {
+                        let content: Vec<u8> = self.buffer.drain(..index + self.delimiter.len()).take(index).collect();
+                        self.write_content(&content).await?;
+                        self.end_part().await?;
+                        self.state = ParseState::Boundary;
+                        Ok(true)
+                    },
+                    None => {
+                        // the end of the buffer may be the start of the delimiter
+                        let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+                        if safe > 0 {
+                            let content: Vec<u8> = self.buffer.drain(..safe).collect();
+                            self.write_content(&content).await?;
+                        }
+                        Ok(false)
+                    }
+                }
+            },
+            ParseState::Epilogue => {
+                self.buffer.clear();
+                Ok(false)
+            },
+        }
+    }
+
+    /// Parses the header lines of a part, each ending in CRLF
+    fn parse_part_headers(raw: &[u8]) -> Result<Part, HttpRequestFailure> {
+        let lines: Vec<LineOrError> = raw.split(|b| *b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty())
+            .map(|line| LineOrError::Line(String::from_utf8_lossy(line).into_owned()))
+            .collect();
+        let headers = HttpRequestParser::fields(&lines)?;
+
+        let (disposition, params) = match headers.get("Content-Disposition") {
+            Some(value) => HttpHeaderBody::parse_content_type(value),
+            None => return Err("Bad request! Multipart part has no Content-Disposition".to_string().into()),
+        };
+        if disposition != "form-data" {
+            return Err(format!("Bad request! Multipart part has Content-Disposition {}", disposition).into());
+        }
+        let mut name: Option<String> = None;
+        let mut filename: Option<String> = None;
+        for (param, value) in params {
+            match param.as_str() {
+                "name" => name = Some(value),
+                "filename" => filename = Some(value),
+                _ => (),
+            }
+        }
+        let name = match name {
+            Some(name) => name,
+            None => return Err("Bad request! Multipart part has no name".to_string().into()),
+        };
+
+        Ok(Part {
+            name,
+            filename,
+            content_type: headers.get("Content-Type").map(|value| value.to_string()),
+            headers,
+            data: PartData::Memory(Vec::new()),
+        })
+    }
+
+    /// Appends to the current part, moving it to a temp file once it is over the memory threshold
+    async fn write_content(&mut self, content: &[u8]) -> Result<(), HttpRequestFailure> {
+        let (part, file) = match &mut self.current {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        if content.len() > self.max_part_size - part.len() {
+            return Err(HttpRequestFailure::new(StatusCode::CONTENT_TOO_LARGE, format!("Multipart part {} is over {} bytes", part.name, self.max_part_size)));
+        }
+
+        match &mut part.data {
+            PartData::Memory(bytes) if bytes.len() + content.len() <= self.memory_threshold => {
+                bytes.extend_from_slice(content);
+            },
+            PartData::Memory(bytes) => {
+                let len = bytes.len() + content.len();
+                let (temp_file, mut opened) = TempFile::create().await.map_err(Self::file_failure)?;
+                opened.write_all(bytes).await.map_err(Self::file_failure)?;
+                opened.write_all(content).await.map_err(Self::file_failure)?;
+                part.data = PartData::File { file: temp_file, len };
+                *file = Some(opened);
+            },
+            PartData::File { len, .. } => {
+                if let Some(opened) = file {
+                    opened.write_all(content).await.map_err(Self::file_failure)?;
+                }
+                *len += content.len();
+            },
+        }
+        Ok(())
+    }
+
+    async fn end_part(&mut self) -> Result<(), HttpRequestFailure> {
+        if let Some((part, file)) = self.current.take() {
+            if let Some(mut opened) = file {
+                opened.flush().await.map_err(Self::file_failure)?;
+            }
+            self.parts.push(part);
+        }
+        Ok(())
+    }
+
+    fn file_failure(e: io::Error) -> HttpRequestFailure {
+        HttpRequestFailure::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write multipart part to a temp file!\n\t{}", e))
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|window| window == needle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MultipartParser, Part};
+    use crate::http::{limits::ServerLimits, request::HttpRequestFailure, status::StatusCode};
+
+    const BOUNDARY: &str = "XyZ";
+
+    /// Body with a part for each (name, filename, content)
+    fn body(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body: Vec<u8> = b"preamble\r\n".to_vec();
+        for (name, filename, content) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            match filename {
+                Some(filename) => body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n", name, filename).as_bytes()),
+                None => body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"\r\n", name).as_bytes()),
+            }
+            body.extend_from_slice(b"\r\n");
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\nepilogue", BOUNDARY).as_bytes());
+        body
+    }
+
+    /// Parses the body pushed as consecutive fragments split at the given offsets
+    async fn parse_split(body: &[u8], splits: &[usize], limits: &ServerLimits) -> Result<Vec<Part>, HttpRequestFailure> {
+        let mut parser = MultipartParser::new(BOUNDARY, limits);
+        let mut start = 0;
+        for end in splits.iter().copied().chain([body.len()]) {
+            parser.push(&body[start..end]).await?;
+            start = end;
+        }
+        parser.finish()
+    }
+
+    async fn parse(body: &[u8], fragment: usize, limits: &ServerLimits) -> Result<Vec<Part>, HttpRequestFailure> {
+        let splits: Vec<usize> = (fragment..body.len()).step_by(fragment).collect();
+        parse_split(body, &splits, limits).await
+    }
+
+    fn parts(result: Result<Vec<Part>, HttpRequestFailure>) -> Vec<Part> {
+        match result {
+            Ok(parts) => parts,
+            Err(failure) => panic!("{} {}", failure.status, failure.fail_reason),
+        }
+    }
+
+    fn status(result: Result<Vec<Part>, HttpRequestFailure>) -> Option<StatusCode> {
+        result.err().map(|failure| failure.status)
+    }
+
+    #[tokio::test]
+    async fn parses_fragments_of_any_size() {
+        let body = body(&[("title", None, b"hello world"), ("upload", Some("a.txt"), b"file content")]);
+        for fragment in [1, 2, 3, 7, 64, body.len()] {
+            let parts = parts(parse(&body, fragment, &ServerLimits::default()).await);
+            assert_eq!(parts.len(), 2, "fragment {}", fragment);
+            assert_eq!(parts[0].name, "title");
+            assert!(!parts[0].is_file());
+            assert_eq!(parts[0].bytes(), Some(&b"hello world"[..]));
+            assert_eq!(parts[1].name, "upload");
+            assert_eq!(parts[1].filename.as_deref(), Some("a.txt"));
+            assert_eq!(parts[1].content_type.as_deref(), Some("application/octet-stream"));
+            assert_eq!(parts[1].bytes(), Some(&b"file content"[..]));
+        }
+    }
+
+    #[tokio::test]
+    async fn parses_delimiter_split_across_pushes() {
+        let body = body(&[("a", None, b"first"), ("b", None, b"second")]);
+        let delimiter = body.windows(7).rposition(|window| window == b"\r\n--XyZ").unwrap();
+        for offset in 0..=7 {
+            let parts = parts(parse_split(&body, &[delimiter + offset], &ServerLimits::default()).await);
+            assert_eq!(parts.len(), 2, "split at {}", offset);
+            assert_eq!(parts[0].bytes(), Some(&b"first"[..]));
+            assert_eq!(parts[1].bytes(), Some(&b"second"[..]));
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_binary_content_byte_for_byte() {
+        let mut content: Vec<u8> = (0..=255).collect();
+        content.extend_from_slice(b"\r\n\r\n--\r\n--Xy\r\n--XyY\r\n");
+        let body = body(&[("bin", Some("b.bin"), &content)]);
+        for fragment in [1, 5, body.len()] {
+            let parts = parts(parse(&body, fragment, &ServerLimits::default()).await);
+            assert_eq!(parts.len(), 1);
+            assert_eq!(parts[0].bytes(), Some(&content[..]));
+        }
+    }
+
+    #[tokio::test]
+    async fn spills_large_parts_to_a_temp_file() {
+        let limits = ServerLimits {
+            part_memory_threshold: 16,
+            ..ServerLimits::default()
+        };
+        let content: Vec<u8> = (0..100u8).collect();
+        let body = body(&[("small", None, b"tiny"), ("large", Some("l.bin"), &content)]);
+        let parts = parts(parse(&body, 10, &limits).await);
+        assert_eq!(parts[0].bytes(), Some(&b"tiny"[..]));
+        assert!(parts[0].path().is_none());
+
+        assert!(parts[1].bytes().is_none());
+        assert_eq!(parts[1].len(), content.len());
+        assert_eq!(parts[1].read_bytes().unwrap(), content);
+        let path = parts[1].path().unwrap().to_path_buf();
+        assert!(path.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let saved = std::env::temp_dir().join(format!("m_server-saved-{}", std::process::id()));
+        parts[1].save_to(&saved).unwrap();
+        assert_eq!(std::fs::read(&saved).unwrap(), content);
+        std::fs::remove_file(&saved).unwrap();
+        drop(parts);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_parts_over_the_size_limit() {
+        let limits = ServerLimits {
+            max_part_size: 8,
+            ..ServerLimits::default()
+        };
+        let body = body(&[("a", None, b"0123456789")]);
+        assert_eq!(status(parse(&body, 3, &limits).await), Some(StatusCode::CONTENT_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn rejects_too_many_parts() {
+        let limits = ServerLimits {
+            max_form_fields: 2,
+            ..ServerLimits::default()
+        };
+        let body = body(&[("a", None, b"1"), ("b", None, b"2"), ("c", None, b"3")]);
+        assert_eq!(status(parse(&body, body.len(), &limits).await), Some(StatusCode::CONTENT_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn rejects_part_headers_over_the_limit() {
+        let limits = ServerLimits {
+            max_header_bytes: 32,
+            ..ServerLimits::default()
+        };
+        let body = body(&[("a_rather_long_field_name", None, b"1")]);
+        assert_eq!(status(parse(&body, body.len(), &limits).await), Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+        // headers that never end are rejected before the whole body is buffered
+        let mut unterminated = format!("--{}\r\n", BOUNDARY).into_bytes();
+        unterminated.extend_from_slice(&[b'x'; 64]);
+        assert_eq!(status(parse(&unterminated, 4, &limits).await), Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn rejects_body_without_closing_boundary() {
+        let body = body(&[("a", None, b"1")]);
+        let truncated = &body[..body.len() - b"--\r\nepilogue".len() - 4];
+        assert_eq!(status(parse(truncated, 4, &ServerLimits::default()).await), Some(StatusCode::BAD_REQUEST));
+        assert!(parts(parse(b"", 1, &ServerLimits::default()).await).is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+use std::str::FromStr;
+
+use super::{
+    response::{HttpResponse, IntoResponse},
+    status::StatusCode,
+};
+
+/// Named segments captured by the router, e.g. `person_id` from `/person/{person_id}`
+///     Kept in the order they appear in the route.
+#[derive(Clone, Default, Debug)]
+pub struct PathParams {
+    params: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathParamError {
+    /// The route has no parameter with this name
+    Missing(String),
+    /// The parameter could not be parsed into the requested type
+    Invalid { name: String, value: String, reason: String },
+}
+
+impl std::fmt::Display for PathParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PathParamError::Missing(name) => write!(f, "Missing path parameter \"{}\"", name),
+            PathParamError::Invalid { name, value, reason } => {
+                write!(f, "Invalid path parameter \"{}\" = \"{}\": {}", name, value, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PathParamError {}
+
+/// 400 Bad Request describing the parameter, so handlers can use `?` on PathParams lookups
+impl IntoResponse for PathParamError {
+    fn into_response(self) -> HttpResponse {
+        (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+    }
+}
+
+impl PathParams {
+    pub fn new() -> PathParams {
+        PathParams {
+            params: Vec::new(),
+        }
+    }
+
+    /// Sets the parameter, replacing any value it already has
+    pub fn insert(&mut self, name: &str, value: String) {
+        match self.params.iter_mut().find(|(param, _)| param == name) {
+            Some((_, existing)) => *existing = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    /// Raw (percent-decoded) value of the parameter
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses the parameter into any FromStr type
+    pub fn parse<T>(&self, name: &str) -> Result<T, PathParamError>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let value = self.get(name).ok_or_else(|| PathParamError::Missing(name.to_string()))?;
+        value.parse::<T>().map_err(|e| PathParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+            reason: e.to_string(),
+        })
+    }
+
+    pub fn get_i64(&self, name: &str) -> Result<i64, PathParamError> {
+        self.parse::<i64>(name)
+    }
+
+    pub fn get_u64(&self, name: &str) -> Result<u64, PathParamError> {
+        self.parse::<u64>(name)
+    }
+
+    #[cfg(feature = "uuid")]
+    pub fn get_uuid(&self, name: &str) -> Result<uuid::Uuid, PathParamError> {
+        self.parse::<uuid::Uuid>(name)
+    }
+
+    /// Parameters in the order they appear in the route
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+}
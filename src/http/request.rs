@@ -1,31 +1,95 @@
-use std::{
-    io::{prelude::*, BufReader},
+use tokio::{
+    io::{AsyncReadExt, BufReader},
     net::TcpStream,
 };
 
 use crate::{router::HttpRoute, LineOrError};
 
-use super::{response::HttpResponse, shared::HttpHeaderBody};
+use super::{
+    chunked::ChunkedDecoder,
+    form::FormData,
+    headers::HttpHeaders,
+    limits::{LimitedLineReader, LineReadError, ServerLimits, ServerTimeouts},
+    multipart::{MultipartParser, Part},
+    params::PathParams,
+    response::HttpResponse,
+    shared::HttpHeaderBody,
+    status::StatusCode,
+    stream::BodySender,
+};
+
+/// Bytes read at a time from a multipart body before they are parsed
+const MULTIPART_READ_BLOCK: usize = 64 * 1024;
 
 #[derive(Hash, PartialEq, Eq, Debug, Clone)]
 pub enum HttpRequestMethod {
     Get,
+    Head,
     Post,
     Put,
     Delete,
     Patch,
-    BadRequest,
+    Options,
+    Trace,
+    Connect,
+    /// Any other valid method token, matched case-sensitively e.g. `PROPFIND`
+    Extension(String),
+}
+
+impl HttpRequestMethod {
+    /// Matches the method token exactly, per RFC 9110 9.1
+    ///     Returns None if the token is empty or has characters not allowed in a token.
+    pub fn from_token(token: &str) -> Option<HttpRequestMethod> {
+        let method = match token {
+            "GET" => HttpRequestMethod::Get,
+            "HEAD" => HttpRequestMethod::Head,
+            "POST" => HttpRequestMethod::Post,
+            "PUT" => HttpRequestMethod::Put,
+            "DELETE" => HttpRequestMethod::Delete,
+            "PATCH" => HttpRequestMethod::Patch,
+            "OPTIONS" => HttpRequestMethod::Options,
+            "TRACE" => HttpRequestMethod::Trace,
+            "CONNECT" => HttpRequestMethod::Connect,
+            _ if Self::is_token(token) => HttpRequestMethod::Extension(token.to_string()),
+            _ => return None,
+        };
+        Some(method)
+    }
+
+    /// tchar from RFC 9110 5.6.2
+    fn is_token(token: &str) -> bool {
+        !token.is_empty() && token.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+impl std::fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HttpVersion::Http10 => write!(f, "HTTP/1.0"),
+            HttpVersion::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
 }
 
 impl std::fmt::Display for HttpRequestMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             HttpRequestMethod::Get => write!(f, "GET"),
+            HttpRequestMethod::Head => write!(f, "HEAD"),
             HttpRequestMethod::Put => write!(f, "PUT"),
             HttpRequestMethod::Patch => write!(f, "PATCH"),
             HttpRequestMethod::Post => write!(f, "POST"),
             HttpRequestMethod::Delete => write!(f, "DELETE"),
-            HttpRequestMethod::BadRequest => write!(f, "BAD_REQUEST"),
+            HttpRequestMethod::Options => write!(f, "OPTIONS"),
+            HttpRequestMethod::Trace => write!(f, "TRACE"),
+            HttpRequestMethod::Connect => write!(f, "CONNECT"),
+            HttpRequestMethod::Extension(method) => write!(f, "{}", method),
         }
     }
 }
@@ -33,18 +97,16 @@ impl std::fmt::Display for HttpRequestMethod {
 pub struct HttpRequestParser;
 
 impl HttpRequestParser {
-    pub fn method(raw: &Vec<LineOrError>) -> HttpRequestMethod {
+    /// Method of the request line, failing with 400 if it is not a valid method token
+    pub fn method(raw: &[LineOrError]) -> Result<HttpRequestMethod, HttpRequestFailure> {
         // attempt to get first row which should contain method & path
         match raw.first() {
-            Some(method) => match method {
-                LineOrError::Line(line) => Self::determine_method(line),
-                LineOrError::Error(_) => HttpRequestMethod::BadRequest,
-            },
-            None => HttpRequestMethod::BadRequest,
+            Some(LineOrError::Line(line)) => Self::determine_method(line),
+            _ => Err("Bad request! Missing request line".to_string().into()),
         }
     }
 
-    pub fn path(raw: &Vec<LineOrError>) -> String {
+    pub fn path(raw: &[LineOrError]) -> String {
         // attempt to get first row which should contain method & path
         match raw.first() {
             Some(method) => match method {
@@ -55,192 +117,470 @@ impl HttpRequestParser {
         }
     }
 
-    pub fn determine_path(line: &str) -> String {
-        match line.split_whitespace().nth(1) {
-            Some(path) => path.to_string(),
-            None => "/".to_string(),
+    pub fn query(raw: &[LineOrError]) -> Option<String> {
+        match raw.first() {
+            Some(LineOrError::Line(line)) => Self::determine_query(line),
+            _ => None,
+        }
+    }
+
+    pub fn version(raw: &[LineOrError]) -> HttpVersion {
+        match raw.first() {
+            Some(LineOrError::Line(line)) => Self::determine_version(line),
+            _ => HttpVersion::Http11,
+        }
+    }
+
+    /// Parses every header line after the request line, see fields
+    pub fn headers(raw: &[LineOrError]) -> Result<HttpHeaders, String> {
+        Self::fields(raw.get(1..).unwrap_or_default())
+    }
+
+    /// Parses field lines, e.g. headers, trailers or the headers of a multipart part, per RFC 9112 5
+    ///     Field values have surrounding whitespace trimmed, repeated fields keep every value.
+    pub fn fields(lines: &[LineOrError]) -> Result<HttpHeaders, String> {
+        let mut headers = HttpHeaders::new();
+        for line_or_error in lines {
+            let line = match line_or_error {
+                LineOrError::Line(line) => line,
+                LineOrError::Error(error) => {
+                    log::error!("Error parsing header! {}", error);
+                    return Err(error.to_string());
+                }
+            };
+            if line.starts_with([' ', '\t']) {
+                return Err(format!("Bad request! Obsolete line folding in header: {}", line));
+            }
+            let (name, value) = match line.split_once(':') {
+                Some(field) => field,
+                None => return Err(format!("Bad request! Malformed header: {}", line)),
+            };
+            if name.is_empty() || name.ends_with([' ', '\t']) {
+                return Err(format!("Bad request! Malformed header name: {}", line));
+            }
+            headers.append(name, value.trim_matches([' ', '\t']));
         }
+        Ok(headers)
     }
 
-    fn determine_method(line: &String) -> HttpRequestMethod {
-        match line {
-            _ if line.as_str().starts_with("GET") => HttpRequestMethod::Get,
-            _ if line.as_str().starts_with("POST") => HttpRequestMethod::Post,
-            _ if line.as_str().starts_with("PUT") => HttpRequestMethod::Put,
-            _ if line.as_str().starts_with("PATCH") => HttpRequestMethod::Patch,
-            _ if line.as_str().starts_with("DELETE") => HttpRequestMethod::Delete,
-            _ => {
-                log::debug!("Unidentified HTTP Request \"{}\"", line);
-                HttpRequestMethod::BadRequest
+    /// Length of the body from the Content-Length header, 0 when absent
+    ///     Repeated headers must all agree, per RFC 9112 6.3
+    pub fn content_length(headers: &HttpHeaders) -> Result<usize, String> {
+        let mut content_length: Option<usize> = None;
+        for value in headers.get_list("Content-Length") {
+            let len = match value.parse::<usize>() {
+                Ok(len) if value.bytes().all(|b| b.is_ascii_digit()) => len,
+                _ => return Err(format!("Bad request! Invalid Content-Length: {}", value)),
+            };
+            if content_length.is_some_and(|prev| prev != len) {
+                return Err("Bad request! Conflicting Content-Length headers".to_string());
             }
+            content_length = Some(len);
         }
+        Ok(content_length.unwrap_or(0))
+    }
+
+    /// Checks the request line is a method, target and version separated by single spaces, per RFC 9112 3
+    ///     Fails with 400 if it is malformed, or 505 if its version is well-formed but not HTTP/1.0 or HTTP/1.1.
+    pub fn validate_request_line(raw: &[LineOrError]) -> Result<(), HttpRequestFailure> {
+        let line = match raw.first() {
+            Some(LineOrError::Line(line)) => line,
+            _ => return Err("Bad request! Missing request line".to_string().into()),
+        };
+        let parts: Vec<&str> = line.split(' ').collect();
+        if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) {
+            return Err(format!("Bad request! Malformed request line: {}", line).into());
+        }
+        match parts[2] {
+            "HTTP/1.0" | "HTTP/1.1" => Ok(()),
+            version if Self::is_http_version(version) => Err(HttpRequestFailure::new(StatusCode::HTTP_VERSION_NOT_SUPPORTED, format!("HTTP version {} is not supported", version))),
+            version => Err(format!("Bad request! Malformed HTTP version: {}", version).into()),
+        }
+    }
+
+    /// HTTP-version from RFC 9112 2.3, `HTTP/` DIGIT `.` DIGIT
+    fn is_http_version(version: &str) -> bool {
+        match version.strip_prefix("HTTP/").map(str::as_bytes) {
+            Some([major, b'.', minor]) => major.is_ascii_digit() && minor.is_ascii_digit(),
+            _ => false,
+        }
+    }
+
+    /// Version of a request line already checked by validate_request_line
+    pub fn determine_version(line: &str) -> HttpVersion {
+        match line.split_whitespace().nth(2) {
+            Some("HTTP/1.0") => HttpVersion::Http10,
+            _ => HttpVersion::Http11,
+        }
+    }
+
+    /// Path of the request target, without the query string
+    pub fn determine_path(line: &str) -> String {
+        match Self::determine_target(line) {
+            Some(target) => match target.split_once('?') {
+                Some((path, _)) => path.to_string(),
+                None => target.to_string(),
+            },
+            None => "/".to_string(),
+        }
+    }
+
+    /// Query string of the request target, without the leading `?`
+    pub fn determine_query(line: &str) -> Option<String> {
+        Self::determine_target(line)
+            .and_then(|target| target.split_once('?'))
+            .map(|(_, query)| query.to_string())
+    }
+
+    /// Request target with any fragment removed
+    fn determine_target(line: &str) -> Option<&str> {
+        line.split_whitespace().nth(1)
+            .map(|target| target.split_once('#').map_or(target, |(target, _)| target))
+    }
+
+    fn determine_method(line: &str) -> Result<HttpRequestMethod, HttpRequestFailure> {
+        // the method is everything before the first space, per RFC 9112 3
+        let method = line.split_once(' ').and_then(|(method, _)| HttpRequestMethod::from_token(method));
+        method.ok_or_else(|| {
+            log::debug!("Unidentified HTTP Request \"{}\"", line);
+            format!("Bad request! Invalid method in request line: {}", line).into()
+        })
     }
 }
 
 pub struct HttpRequestFailure {
-    pub tcp_stream: TcpStream,
+    /// Status the failure is answered with
+    pub status: StatusCode,
     pub fail_reason: String,
 }
 
 impl HttpRequestFailure {
-    pub fn respond(&mut self, http_res: HttpResponse) {
-        match self.tcp_stream.write_all(http_res.response.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to write to TcpStream in respond!\n\t{}", e);
-            }
+    pub fn new(status: StatusCode, fail_reason: String) -> HttpRequestFailure {
+        HttpRequestFailure {
+            status,
+            fail_reason,
         }
     }
+}
 
-    pub fn respond_with_body(&mut self, http_res: &HttpResponse, body: &str) {
-        let mut res_with_body: String = String::new();
-        res_with_body.push_str(&http_res.response);
-        res_with_body.push_str(body);
-        match self.tcp_stream.write_all(res_with_body.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to write to TcpStream in respond with body!\n\t{}", e);
-            }
-        };
+impl From<String> for HttpRequestFailure {
+    /// Any failure without a more specific status is a 400 Bad Request
+    fn from(fail_reason: String) -> Self {
+        HttpRequestFailure::new(StatusCode::BAD_REQUEST, fail_reason)
     }
 }
 
 pub struct HttpRequest {
-    pub tcp_stream: TcpStream,
     pub route: HttpRoute,
+    pub version: HttpVersion,
     pub peer_addr: Option<String>,
+    pub headers: HttpHeaders,
+    /// Fields sent after a chunked body, empty for any other body
+    pub trailers: HttpHeaders,
     pub body: HttpHeaderBody,
+    pub(crate) path_params: PathParams,
+    query_string: Option<String>,
+    query: FormData,
     responded: bool,
+    response: Option<HttpResponse>,
 }
 
 impl HttpRequest {
-    pub fn new(stream: TcpStream) -> Result<HttpRequest, HttpRequestFailure> {
-        let h_body = Self::gen_raw_req(stream);
-        match h_body {
-            Ok((header_body, stream)) => {
+    /// Parses the next request from the connection.
+    ///     Bytes after the request are left in the reader for the next (pipelined) request.
+    pub async fn new(reader: &mut BufReader<TcpStream>, limits: &ServerLimits, timeouts: &ServerTimeouts) -> Result<HttpRequest, HttpRequestFailure> {
+        let peer_addr: Option<String> = match &reader.get_ref().peer_addr() {
+            Ok(addr) => Some(addr.ip().to_string()),
+            Err(e) => {
+                log::error!("Socket Address for peer failed! \n\t{}", e);
+                None
+            }
+        };
+
+        match Self::gen_raw_req(reader, limits, timeouts).await {
+            Ok((method, headers, trailers, header_body)) => {
                 let route: HttpRoute = HttpRoute {
-                    method: HttpRequestParser::method(&header_body.lines),
+                    method,
                     path: HttpRequestParser::path(&header_body.lines),
                 };
 
-                let peer_addr: Option<String> = match &stream.peer_addr() {
-                    Ok(addr) => Some(addr.ip().to_string()),
-                    Err(e) => {
-                        log::error!("Socket Address for peer failed! \n\t{}", e);
-                        None
-                    }
+                let query_string: Option<String> = HttpRequestParser::query(&header_body.lines);
+                let query: FormData = match &query_string {
+                    Some(raw) => FormData::parse(raw.as_bytes()),
+                    None => FormData::new(),
                 };
 
                 Ok(HttpRequest {
-                    tcp_stream: stream,
                     route,
+                    version: HttpRequestParser::version(&header_body.lines),
                     peer_addr,
+                    headers,
+                    trailers,
                     body: header_body,
+                    path_params: PathParams::new(),
+                    query_string,
+                    query,
                     responded: false,
+                    response: None,
                 })
             },
-            Err((reason_str, stream)) => {
-                Err(
-                    HttpRequestFailure {
-                        tcp_stream: stream,
-                        fail_reason: reason_str,
-                    }
-                   )
-            }
+            Err(http_fail) => Err(http_fail),
         }
     }
 
+    /// Parameters captured from the path by the matched route
+    ///     e.g. `request.path_params().get_i64("person_id")` for `/person/{person_id}`
+    pub fn path_params(&self) -> &PathParams {
+        &self.path_params
+    }
+
+    /// Decoded query string parameters, e.g. `request.query().get_all("tag")` for `?tag=a&tag=b`
+    pub fn query(&self) -> &FormData {
+        &self.query
+    }
+
+    /// Fields of a url-encoded or multipart form body, e.g. `request.form().map(|form| form.get_list("ids"))`
+    ///     None if the body is not a form.
+    pub fn form(&self) -> Option<&FormData> {
+        self.body.body_params.as_ref()
+    }
+
+    /// Raw query string as sent, without the leading `?`
+    pub fn query_string(&self) -> Option<&str> {
+        self.query_string.as_deref()
+    }
+
+    /// Body exactly as received, for any Content-Type but multipart/form-data, see parts
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body.raw
+    }
+
+    /// Parts of a multipart/form-data body, in the order sent
+    pub fn parts(&self) -> &[Part] {
+        &self.body.parts
+    }
+
+    /// First part of a multipart/form-data body with the field name
+    pub fn part(&self, name: &str) -> Option<&Part> {
+        self.body.parts.iter().find(|part| part.name == name)
+    }
+
+    /// Body as UTF-8 text, fails if the body is not valid UTF-8
+    pub fn body_text(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.body.raw)
+    }
+
+    /// Deserializes the JSON body, see JsonError::to_response for answering a failure
+    #[cfg(feature = "serde")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, super::json::JsonError> {
+        serde_json::from_slice(&self.body.raw).map_err(super::json::JsonError::from)
+    }
+
     pub fn responded(&self) -> bool {
         self.responded
     }
 
+    /// Whether the client wants the connection kept open after the response, per RFC 9112 9.3
+    ///     HTTP/1.1 is persistent unless "close" is sent, HTTP/1.0 only if "keep-alive" is sent.
+    pub fn keep_alive(&self) -> bool {
+        let options = self.headers.get_list("Connection");
+        if options.iter().any(|option| option.eq_ignore_ascii_case("close")) {
+            return false;
+        }
+        match self.version {
+            HttpVersion::Http11 => true,
+            HttpVersion::Http10 => options.iter().any(|option| option.eq_ignore_ascii_case("keep-alive")),
+        }
+    }
+
     pub fn println_req(&self) {
         let mut route_str: String = "".to_string();
         route_str.push_str(self.route.to_string().as_str());
         log::info!("{}", route_str);
     }
 
+    /// Queues the response for this request.
+    ///     It is written to the TcpStream once the handler returns.
     pub fn respond(&mut self, http_res: HttpResponse) {
         if self.responded {
             log::warn!("Attempted to respond to request twice!");
             return;
         }
-        match self.tcp_stream.write_all(http_res.response.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to write to TcpStream in respond!\n\t{}", e);
-            }
-        }
-        match self.tcp_stream.shutdown(std::net::Shutdown::Both) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to shutdown TcpStream in respond!\n\t{}", e);
-            }
-        }
+        self.response = Some(http_res);
         self.responded = true;
     }
 
+    /// Queues the response with the given body for this request.
+    ///     It is written to the TcpStream once the handler returns.
     pub fn respond_with_body(&mut self, http_res: &HttpResponse, body: &str) {
         if self.responded {
             log::warn!("Attempted to respond to request twice!");
             return;
         }
-        let mut res_with_body: String = String::new();
-        res_with_body.push_str(&http_res.response);
-        res_with_body.push_str(body);
-        match self.tcp_stream.write_all(res_with_body.as_bytes()) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to write to TcpStream in respond with body!\n\t{}", e);
+        self.response = Some(http_res.clone().with_body(body));
+        self.responded = true;
+    }
+
+    /// Queues the response with a body streamed as it is produced.
+    ///     Chunks sent before the handler returns are buffered, so produce them from a spawned task or thread.
+    ///     The body ends once the returned sender (and all its clones) are dropped.
+    ///     A sender going longer than the write timeout between chunks has its connection closed, leaving the body incomplete.
+    ///
+    /// # Arguments
+    ///
+    /// * `http_res` - Status and headers of the response, its body is ignored
+    /// * `capacity` - Chunks buffered before the sender waits on the client to read
+    pub fn respond_stream(&mut self, http_res: HttpResponse, capacity: usize) -> BodySender {
+        let (sender, stream) = super::stream::channel(capacity);
+        if self.responded {
+            log::warn!("Attempted to respond to request twice!");
+            return sender;
+        }
+        self.response = Some(http_res.with_stream(stream));
+        self.responded = true;
+        sender
+    }
+
+    /// Queues the response, replacing any already queued, e.g. with the result of the middleware pipeline
+    pub(crate) fn set_response(&mut self, http_res: HttpResponse) {
+        self.response = Some(http_res);
+        self.responded = true;
+    }
+
+    /// Queued response, if the request has been responded to
+    pub(crate) fn response_mut(&mut self) -> Option<&mut HttpResponse> {
+        self.response.as_mut()
+    }
+
+    /// Takes the queued response, leaving nothing behind to write.
+    pub(crate) fn take_response(&mut self) -> Option<HttpResponse> {
+        self.response.take()
+    }
+
+    /// Generates HTTP request headers into Vec<LineOrError>, then reads the body they describe
+    async fn gen_raw_req(buf_reader: &mut BufReader<TcpStream>, limits: &ServerLimits, timeouts: &ServerTimeouts) -> Result<(HttpRequestMethod, HttpHeaders, HttpHeaders, HttpHeaderBody), HttpRequestFailure> {
+        let http_request: Vec<LineOrError> = match tokio::time::timeout(timeouts.header_read, Self::read_head(buf_reader, limits)).await {
+            Ok(head) => head?,
+            Err(_) => {
+                log::warn!("Header was not received within {} seconds", timeouts.header_read.as_secs());
+                return Err(HttpRequestFailure::new(StatusCode::REQUEST_TIMEOUT, "Header read timed out".to_string()));
             }
         };
-        match self.tcp_stream.shutdown(std::net::Shutdown::Both) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to shutdown TcpStream in respond!\n\t{}", e);
+
+        HttpRequestParser::validate_request_line(&http_request)?;
+        // an invalid method is rejected here, so the router only sees methods a route could have
+        let method = HttpRequestParser::method(&http_request)?;
+        let headers = HttpRequestParser::headers(&http_request)?;
+        let mut multipart = MultipartParser::for_request(&headers, limits)?;
+        // trailers share the header's limits, so only what the header left of them is available
+        let fields = &http_request[1..];
+        let field_bytes: usize = fields.iter()
+            .map(|field| match field {
+                LineOrError::Line(line) => line.len(),
+                LineOrError::Error(_) => 0,
+            })
+            .sum();
+        let body_limits = ServerLimits {
+            max_header_bytes: limits.max_header_bytes - field_bytes,
+            max_headers: limits.max_headers - fields.len(),
+            ..limits.clone()
+        };
+        let (raw, trailers) = match tokio::time::timeout(timeouts.body_read, Self::read_body(buf_reader, &headers, &body_limits, multipart.as_mut())).await {
+            Ok(body) => body?,
+            Err(_) => {
+                log::warn!("Body was not received within {} seconds", timeouts.body_read.as_secs());
+                return Err(HttpRequestFailure::new(StatusCode::REQUEST_TIMEOUT, "Body read timed out".to_string()));
             }
-        }
-        self.responded = true;
+        };
+        let parts = match multipart {
+            Some(parser) => parser.finish()?,
+            None => Vec::new(),
+        };
+        let body = HttpHeaderBody::new(http_request, &headers, raw, parts, limits)?;
+        Ok((method, headers, trailers, body))
     }
 
-    /// Generates HTTP request headers into Vec<LineOrError>
-    fn gen_raw_req(mut stream: TcpStream) -> Result<(HttpHeaderBody, TcpStream), (String, TcpStream)> {
-        let mut buf_reader = BufReader::new(&mut stream);
-        let mut content_length: usize = 0;
-        let mut got_content_length = false;
-        let length_str = "Content-Length:";
-        let http_request: Vec<LineOrError> = buf_reader
-            .by_ref()
-            .lines()
-            .map(|result| match result {
-                Ok(res) => {
-                    if !got_content_length && res.starts_with(length_str) {
-                        content_length = match &res[(length_str.len())..(res.len())].replace(' ', "").parse::<usize>() {
-                            Ok(len) => len.clone(),
-                            Err(e) => {
-                                log::error!("{} Header Len {}", e, res);
-                                0
-                            }
-                        };
-                        got_content_length = true;
+    /// Reads the request line and header lines, up to the empty line ending the header
+    async fn read_head(buf_reader: &mut BufReader<TcpStream>, limits: &ServerLimits) -> Result<Vec<LineOrError>, HttpRequestFailure> {
+        let mut http_request: Vec<LineOrError> = Vec::new();
+        let mut header_bytes: usize = 0;
+        loop {
+            let is_request_line = http_request.is_empty();
+            let max_len = match is_request_line {
+                true => limits.max_request_line,
+                false => limits.max_header_bytes - header_bytes,
+            };
+            match LimitedLineReader::read_line(buf_reader, max_len).await {
+                // a connection closed before sending anything is closed without an answer by HttpConnection, which waits for the first byte
+                Ok(None) | Err(LineReadError::UnexpectedEof) => {
+                    return Err("Bad request! Connection closed before the header ended".to_string().into());
+                },
+                Ok(Some(res)) => {
+                    if res.is_empty() {
+                        break;
                     }
-                    LineOrError::Line(res)
-                }
+                    if !is_request_line {
+                        header_bytes += res.len();
+                        if http_request.len() > limits.max_headers {
+                            return Err(HttpRequestFailure::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, format!("More than {} headers were sent", limits.max_headers)));
+                        }
+                    }
+                    http_request.push(LineOrError::Line(res));
+                },
+                Err(LineReadError::TooLong) if is_request_line => {
+                    return Err(HttpRequestFailure::new(StatusCode::URI_TOO_LONG, format!("Request line is over {} bytes", limits.max_request_line)));
+                },
+                Err(LineReadError::TooLong) => {
+                    return Err(HttpRequestFailure::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, format!("Headers are over {} bytes", limits.max_header_bytes)));
+                },
                 Err(error) => {
                     log::error!("Error reading line:\n\t{}", error);
-                    LineOrError::Error(error.to_string())
+                    http_request.push(LineOrError::Error(error.to_string()));
+                    break;
                 }
-            })
-        .take_while(|line| match line {
-            LineOrError::Line(line) => !line.is_empty(),
-            LineOrError::Error(_) => true,
-        })
-        .collect();
+            }
+        }
+        Ok(http_request)
+    }
+
+    /// Reads the body framed by Transfer-Encoding or Content-Length, per RFC 9112 6.3
+    ///     Sending both is rejected, as the framing is ambiguous and could be used to smuggle requests.
+    ///     A multipart body is fed to its parser as it arrives instead of being returned.
+    async fn read_body(buf_reader: &mut BufReader<TcpStream>, headers: &HttpHeaders, limits: &ServerLimits, multipart: Option<&mut MultipartParser>) -> Result<(Vec<u8>, HttpHeaders), HttpRequestFailure> {
+        let transfer_codings = headers.get_list("Transfer-Encoding");
+        if !transfer_codings.is_empty() {
+            if headers.contains("Content-Length") {
+                return Err("Bad request! Both Transfer-Encoding and Content-Length were sent".to_string().into());
+            }
+            // no other coding is decoded, so only a lone chunked leaves the body as sent, per RFC 9112 6.1
+            if transfer_codings.len() != 1 || !transfer_codings[0].eq_ignore_ascii_case("chunked") {
+                return Err(HttpRequestFailure::new(StatusCode::NOT_IMPLEMENTED, format!("Unsupported Transfer-Encoding: {}", transfer_codings.join(", "))));
+            }
+            return ChunkedDecoder::read_body(buf_reader, limits, multipart).await;
+        }
+
+        let content_length = HttpRequestParser::content_length(headers)?;
+        if content_length > limits.max_body {
+            return Err(HttpRequestFailure::new(StatusCode::CONTENT_TOO_LARGE, format!("Content-Length {} is over {} bytes", content_length, limits.max_body)));
+        }
+        if let Some(parser) = multipart {
+            let mut remaining = content_length;
+            let mut block: Vec<u8> = vec![0; remaining.min(MULTIPART_READ_BLOCK)];
+            while remaining > 0 {
+                let len = remaining.min(block.len());
+                if let Err(e) = buf_reader.read_exact(&mut block[..len]).await {
+                    return Err(format!("Connection closed before the body was received!\n\t{}", e).into());
+                }
+                parser.push(&block[..len]).await?;
+                remaining -= len;
+            }
+            return Ok((Vec::new(), HttpHeaders::new()));
+        }
 
-        let body_o = HttpHeaderBody::new(http_request, buf_reader, content_length);
-        match body_o {
-            Ok(body) => Ok((body, stream)),
-            Err(reason) => Err((reason, stream)),
+        let mut raw: Vec<u8> = vec![0; content_length];
+        match buf_reader.read_exact(&mut raw).await {
+            Ok(_) => Ok((raw, HttpHeaders::new())),
+            Err(e) => Err(format!("Connection closed before the body was received!\n\t{}", e).into()),
         }
     }
 }
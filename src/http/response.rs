@@ -1,57 +1,269 @@
-const HEADER_SERVER_NAME: &'static str = "Server: m_server/0.1.4-alpha";
-const HEADER_CONTENT_TYPE: &'static str = "Content-Type: application/json";
+use super::{headers::HttpHeaders, request::HttpRequest, status::StatusCode, stream::BodyStream};
 
+const HEADER_SERVER_NAME: &'static str = concat!("m_server/", env!("CARGO_PKG_VERSION"));
+const HEADER_CONTENT_TYPE: &'static str = "application/json";
+
+#[derive(Clone)]
 pub struct HttpResponse {
-    pub response: String,
+    pub status: StatusCode,
+    pub headers: HttpHeaders,
+    pub body: Vec<u8>,
+    /// Streamed body sent with chunked framing, replaces body when set
+    pub stream: Option<BodyStream>,
 }
 
 impl HttpResponse {
     const HTTP_VER: &'static str = "HTTP/1.1";
-    const HTTP_PAD: &'static str = "\r\n\r\n";
-    const RESPONSE_OK: &'static str = "200 OK";
-    const RESPONSE_NOT_FOUND: &'static str = "404 Not Found";
-    const RESPONSE_ERROR: &'static str = "500 Internal Server Error";
-    const BAD_REQUEST: &'static str = "400 Bad Request";
-    const RESPONSE_CREATED: &'static str = "201 Created";
-    const RESPONSE_ACCEPTED: &'static str = "Accepted";
-    
-    pub fn new(response: String) -> HttpResponse {
+    const CRLF: &'static str = "\r\n";
+
+    /// Creates an empty response with the default Server header
+    ///     A Content-Type is only set once a body is attached, see with_body.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - Any StatusCode, see StatusCode::from_u16 for unregistered codes
+    pub fn new(status: StatusCode) -> HttpResponse {
+        let mut headers = HttpHeaders::new();
+        headers.insert("Server", self::HEADER_SERVER_NAME);
         HttpResponse {
-            response,
+            status,
+            headers,
+            body: Vec::new(),
+            stream: None,
         }
     }
 
     pub fn bad_request() -> HttpResponse {
-        let response_str: String = Self::status_message(Self::BAD_REQUEST);
-        HttpResponse::new(response_str)
+        HttpResponse::new(StatusCode::BAD_REQUEST)
     }
 
     pub fn ok() -> HttpResponse {
-        let response_str: String = Self::status_message(Self::RESPONSE_OK);
-        HttpResponse::new(response_str)
+        HttpResponse::new(StatusCode::OK)
     }
 
     pub fn not_found() -> HttpResponse {
-        let response_str: String = Self::status_message(Self::RESPONSE_NOT_FOUND);
-        HttpResponse::new(response_str)
+        HttpResponse::new(StatusCode::NOT_FOUND)
     }
 
     pub fn created() -> HttpResponse {
-        let response_str: String = Self::status_message(Self::RESPONSE_CREATED);
-        HttpResponse::new(response_str)
+        HttpResponse::new(StatusCode::CREATED)
     }
 
     pub fn accepted() -> HttpResponse {
-        let response_str: String = Self::status_message(Self::RESPONSE_ACCEPTED);
-        HttpResponse::new(response_str)
+        HttpResponse::new(StatusCode::ACCEPTED)
     }
 
     pub fn error() -> HttpResponse {
-        let response_str: String = Self::status_message(Self::RESPONSE_ERROR);
-        HttpResponse::new(response_str)
+        HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Sets the header to a single value, replacing any existing values
+    pub fn with_header(mut self, name: &str, value: &str) -> HttpResponse {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the body, accepts a String, &str or bytes
+    ///     A non-empty body is sent as application/json unless a Content-Type is already set.
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> HttpResponse {
+        self.body = body.into();
+        if !self.body.is_empty() {
+            self.default_content_type();
+        }
+        self
+    }
+
+    /// Streams the body instead, see stream::channel
+    ///     It is sent as application/json unless a Content-Type is already set.
+    pub fn with_stream(mut self, stream: BodyStream) -> HttpResponse {
+        self.stream = Some(stream);
+        self.default_content_type();
+        self
+    }
+
+    /// Sets the default Content-Type, unless one is set or the status forbids a body
+    fn default_content_type(&mut self) {
+        if !self.status.forbids_body() && !self.headers.contains("Content-Type") {
+            self.headers.insert("Content-Type", self::HEADER_CONTENT_TYPE);
+        }
+    }
+
+    /// Serializes the response for the wire.
+    ///     Content-Length is always derived from the body, any set by hand is replaced.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let forbids_body = self.status.forbids_body();
+        let mut bytes: Vec<u8> = match forbids_body {
+            true => self.head(None),
+            false => self.head(Some(&format!("Content-Length: {}", self.body.len()))),
+        };
+        if !forbids_body {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+
+    /// Serializes the response as to_bytes does, without the body, as the answer to a HEAD request
+    pub fn to_head_bytes(&self) -> Vec<u8> {
+        match self.status.forbids_body() || self.stream.is_some() {
+            true => self.head(None),
+            false => self.head(Some(&format!("Content-Length: {}", self.body.len()))),
+        }
+    }
+
+    /// Serializes the status line and headers for a streamed body
+    ///
+    /// # Arguments
+    ///
+    /// * `chunked` - Frame the body with Transfer-Encoding: chunked, otherwise it ends when the connection closes
+    pub fn to_stream_head(&self, chunked: bool) -> Vec<u8> {
+        match chunked && !self.status.forbids_body() {
+            true => self.head(Some("Transfer-Encoding: chunked")),
+            false => self.head(None),
+        }
+    }
+
+    /// Status line and headers, with framing headers replaced by the given one
+    fn head(&self, framing: Option<&str>) -> Vec<u8> {
+        let mut head: String = format!("{} {}{}", Self::HTTP_VER, self.status, Self::CRLF);
+        for (name, value) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Transfer-Encoding") {
+                continue;
+            }
+            if !Self::valid_field(name, value) {
+                log::warn!("Header \"{}\" contains invalid characters and was not sent!", name);
+                continue;
+            }
+            head.push_str(&format!("{}: {}{}", name, value, Self::CRLF));
+        }
+        if let Some(framing) = framing {
+            head.push_str(framing);
+            head.push_str(Self::CRLF);
+        }
+        head.push_str(Self::CRLF);
+        head.into_bytes()
+    }
+
+    /// Guards against response splitting through header names or values
+    fn valid_field(name: &str, value: &str) -> bool {
+        !name.is_empty()
+            && name.bytes().all(|b| b.is_ascii_graphic() && b != b':')
+            && !value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0)
+    }
+}
+
+/// Values a handler can return to answer its request.
+///     A handler returning `()` keeps whatever it queued with respond, or 200 OK if it queued nothing.
+pub trait IntoResponse {
+    fn into_response(self) -> HttpResponse;
+
+    /// Queues the response on the request, replacing any the handler queued itself
+    fn respond_to(self, http_req: &mut HttpRequest)
+    where
+        Self: Sized,
+    {
+        http_req.set_response(self.into_response());
+    }
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self) -> HttpResponse {
+        self
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::ok()
+    }
+
+    fn respond_to(self, _http_req: &mut HttpRequest) {}
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::new(self)
+    }
+}
+
+/// 200 OK with a text/plain body
+impl IntoResponse for String {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::ok()
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(self)
+    }
+}
+
+/// 200 OK with a text/plain body
+impl IntoResponse for &'static str {
+    fn into_response(self) -> HttpResponse {
+        self.to_string().into_response()
+    }
+}
+
+/// 200 OK with an application/octet-stream body
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::ok()
+            .with_header("Content-Type", "application/octet-stream")
+            .with_body(self)
+    }
+}
+
+/// Response of the value with its status replaced, e.g. `(StatusCode::CREATED, String::from("done"))`
+///     A status forbidding a body, such as 204, also drops the Content-Type of the value.
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> HttpResponse {
+        let mut http_res = self.1.into_response();
+        http_res.status = self.0;
+        if http_res.status.forbids_body() {
+            http_res.headers.remove("Content-Type");
+        }
+        http_res
+    }
+}
+
+/// Lets handlers use `?`, the error is answered with its own response
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> HttpResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(error) => error.into_response(),
+        }
+    }
+
+    fn respond_to(self, http_req: &mut HttpRequest) {
+        match self {
+            Ok(value) => value.respond_to(http_req),
+            Err(error) => error.respond_to(http_req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpResponse, IntoResponse};
+    use crate::http::status::StatusCode;
+
+    fn serialized(http_res: &HttpResponse) -> String {
+        String::from_utf8(http_res.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn sets_a_content_type_only_with_a_body() {
+        assert!(!serialized(&HttpResponse::ok()).contains("Content-Type"));
+        assert!(!serialized(&StatusCode::NOT_FOUND.into_response()).contains("Content-Type"));
+        assert!(!serialized(&HttpResponse::ok().with_body("")).contains("Content-Type"));
+        assert!(serialized(&HttpResponse::ok().with_body("{}")).contains("Content-Type: application/json\r\n"));
+        let text = HttpResponse::ok().with_header("Content-Type", "text/plain").with_body("hi");
+        assert_eq!(text.headers.get_all("Content-Type"), vec!["text/plain"]);
     }
 
-    fn status_message(status_code: &str) -> String {
-        format!("{} {}\n{}\n{}{}", Self::HTTP_VER, status_code, self::HEADER_SERVER_NAME, self::HEADER_CONTENT_TYPE, Self::HTTP_PAD)
+    #[test]
+    fn skips_the_content_type_when_the_status_forbids_a_body() {
+        assert!(!serialized(&HttpResponse::new(StatusCode::NO_CONTENT).with_body("{}")).contains("Content-Type"));
+        assert!(!serialized(&HttpResponse::new(StatusCode::NOT_MODIFIED).with_body("{}")).contains("Content-Type"));
+        assert!(!serialized(&(StatusCode::NO_CONTENT, "done").into_response()).contains("Content-Type"));
+        assert!(serialized(&(StatusCode::CREATED, "done").into_response()).contains("Content-Type: text/plain; charset=utf-8\r\n"));
     }
 }
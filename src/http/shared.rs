@@ -1,201 +1,142 @@
-use std::{collections::HashMap, net::TcpStream, io::{BufReader, Read}, str::from_utf8};
-
 use crate::LineOrError;
 
-use super::decoder::HttpUrlDecoder;
+use super::{
+    form::FormData,
+    headers::HttpHeaders,
+    limits::ServerLimits,
+    multipart::Part,
+    request::HttpRequestFailure,
+    status::StatusCode,
+};
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum HttpBodyType {
     FormData,
     UrlEncoded,
+    Json,
 }
 
 impl HttpBodyType {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             HttpBodyType::FormData => "multipart/form-data",
             HttpBodyType::UrlEncoded => "application/x-www-form-urlencoded",
+            HttpBodyType::Json => "application/json",
         }
     }
+
+    /// Matches application/json and structured syntax suffix types such as application/problem+json
+    pub(crate) fn is_json(media_type: &str) -> bool {
+        media_type == HttpBodyType::Json.to_str()
+            || (media_type.contains('/') && media_type.ends_with("+json"))
+    }
 }
 
 pub struct HttpHeaderBody {
     pub lines: Vec<LineOrError>,
     pub header_len: usize, 
     pub body_type: Option<HttpBodyType>,
-    pub body_params: Option<HashMap<String, String>>,
+    /// Fields of a url-encoded or multipart form body, file uploads are only available as parts
+    pub body_params: Option<FormData>,
+    /// Body exactly as received, empty for multipart/form-data which is parsed into parts as it arrives
+    pub raw: Vec<u8>,
+    /// Parts of a multipart/form-data body, in the order sent
+    pub parts: Vec<Part>,
 }
 
 impl HttpHeaderBody {
-    pub fn new(lines: Vec<LineOrError>, buf_reader: BufReader<&mut TcpStream>, header_len: usize) -> Result<HttpHeaderBody, String> {
-        let mut body_type: Option<HttpBodyType> = None;
-        let mut boundary: Option<String> = None;
-        for line_or_error in &lines {
-            match line_or_error {
-                LineOrError::Line(line) => {
-                    if line.starts_with("Content-Type: ") {
-                        let content_type = line.trim_start_matches("Content-Type: ").trim();
-                        body_type = match content_type {
-                            ct if ct.starts_with(HttpBodyType::FormData.to_str()) => {
-                                Some(HttpBodyType::FormData)
-                            },
-                            ct if ct.starts_with(HttpBodyType::UrlEncoded.to_str()) => Some(HttpBodyType::UrlEncoded),
-                            _ => None,
-                        };
-                        if body_type.is_none() {
-                            let mut message = "Bad request! Unrecognized Content-Type: ".to_string();
-                            message.push_str(content_type);
-                            return Err(message);
-                        }
-                        if let Some(bod) = body_type {
-                            if bod == HttpBodyType::FormData {
-                                boundary = Some(content_type.trim_start_matches(HttpBodyType::FormData.to_str()).trim().trim_start_matches("; boundary=").trim().to_string())
-                            }
-                        }
-                        break;
-                    }
-                },
-                LineOrError::Error(error) => {
-                    log::error!("Error parsing header! {}", error);
-                    return Err(error.to_string());
-                }
-            }
-        }
-
-        match body_type {
-            Some(body) => {
-                Ok(HttpHeaderBody {
-                    lines,
-                    header_len,
-                    body_type: Some(body),
-                    body_params: Self::gen_params(buf_reader, header_len, body, boundary),
-                })
+    /// Builds the body from what was received, multipart bodies arrive already parsed into parts.
+    ///     Fails with 413 if a url-encoded body has more fields than the limits allow.
+    pub fn new(lines: Vec<LineOrError>, headers: &HttpHeaders, raw: Vec<u8>, parts: Vec<Part>, limits: &ServerLimits) -> Result<HttpHeaderBody, HttpRequestFailure> {
+        let body_type: Option<HttpBodyType> = match headers.get("Content-Type") {
+            Some(content_type) => match Self::parse_content_type(content_type).0.as_str() {
+                ct if ct == HttpBodyType::FormData.to_str() => Some(HttpBodyType::FormData),
+                ct if ct == HttpBodyType::UrlEncoded.to_str() => Some(HttpBodyType::UrlEncoded),
+                ct if HttpBodyType::is_json(ct) => Some(HttpBodyType::Json),
+                _ => None,
             },
-            None => {
-                Ok(HttpHeaderBody {
-                    lines,
-                    header_len,
-                    body_type: None,
-                    body_params: None,
-                })
-            }
-        }
-    }
+            None => None,
+        };
 
-    fn gen_params(buf_reader: BufReader<&mut TcpStream>, header_len: usize, body_type: HttpBodyType, boundary: Option<String>) -> Option<HashMap<String, String>> {
-        if header_len == 0 {
-            return None;
-        }
-        match body_type {
-            HttpBodyType::FormData => {
-                Some(Self::gen_params_form_data(buf_reader, header_len, boundary))
-            },
-            HttpBodyType::UrlEncoded => {
-                Some(Self::gen_params_url_encoded(buf_reader, header_len))
+        if body_type == Some(HttpBodyType::UrlEncoded) {
+            let fields = raw.split(|b| *b == b'&').filter(|pair| !pair.is_empty()).count();
+            if fields > limits.max_form_fields {
+                return Err(HttpRequestFailure::new(StatusCode::CONTENT_TOO_LARGE, format!("Form has {} fields, over the limit of {}", fields, limits.max_form_fields)));
             }
         }
-    }
-
-    fn gen_params_form_data(mut buf_reader: BufReader<&mut TcpStream>, header_len: usize, boundary: Option<String>) -> HashMap<String, String> {
-            let mut buffer = Vec::new();
-            let mut key = String::new();
-            let mut value = String::new();
-            let mut in_boundary = false;
-            let mut bad_boundary = false;
-            let mut params: HashMap<String, String> = HashMap::new();
-
-            if let Some(bound) = boundary {
-                for _ in 0..header_len {
-                    let mut byte = [0];
-                    if buf_reader.read_exact(&mut byte).is_err() {
-                        // Handle read error or end of stream
-                        break;
-                    }
 
-                    // Check for newline to process the buffered line
-                    if byte[0] == b'\n' {
-                        let line = from_utf8(&buffer).unwrap_or_default().trim().to_string();
-
-                        if line.contains(&bound) {
-                            if !key.is_empty() {
-                                params.insert(key.clone(), value.clone());
-                            }
-                            in_boundary = true;
-                            bad_boundary = false;
-                            key.clear();
-                            value.clear();
-                        } else if in_boundary {
-                            in_boundary = false;
-                            if let Some(kv) = line.trim_start_matches("Content-Disposition: form-data; name=\"").split_once('"') {
-                                key = kv.0.to_string();
-                            } else {
-                                bad_boundary = true;
-                            }
-                        } else if !bad_boundary && !line.is_empty() {
-                            if !value.is_empty() {
-                                value.push('\n');
-                            }
-                            value.push_str(&line);
-                        // add in plain new line
-                        } else if !bad_boundary {
-                            if !value.is_empty() {
-                                value.push('\n');
-                            }
-                        }
-
-                        buffer.clear();
-                    } else {
-                        buffer.push(byte[0]);
-                    }
-                }
-            }
-
-            // Insert the last key-value pair if there is one
-            if !key.is_empty() && !value.is_empty() {
-                params.insert(key, value);
-            }
-
-        params
+        let body_params = match body_type {
+            Some(body) => Self::gen_params(&raw, &parts, body),
+            None => None,
+        };
+
+        Ok(HttpHeaderBody {
+            lines,
+            header_len: raw.len(),
+            body_type,
+            body_params,
+            raw,
+            parts,
+        })
     }
 
-    fn gen_params_url_encoded(mut buf_reader: BufReader<&mut TcpStream>, header_len: usize) -> HashMap<String, String> {
-        let mut params: HashMap<String, String> = HashMap::new();
-        let mut key = String::new();
-        let mut value = String::new();
-        let mut is_key = true;
-
-        for _ in 0..header_len {
-            let mut byte = [0];
-            if buf_reader.read_exact(&mut byte).is_err() {
-                log::error!("Failed to read byte from TcpStream!");
-                break;
-            }
+    /// Splits a Content-Type value into its lowercased media type and parameters, per RFC 9110 8.3.1
+    ///     Parameter names are lowercased and quoted values are unquoted, `;` within quotes does not split.
+    ///     Also used for Content-Disposition, which has the same shape.
+    pub fn parse_content_type(content_type: &str) -> (String, Vec<(String, String)>) {
+        let mut parts = Self::split_unquoted(content_type, ';').into_iter();
+        let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value.strip_prefix('"')
+                    .and_then(|quoted| quoted.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim().to_ascii_lowercase(), value.to_string())
+            })
+            .collect();
+        (media_type, params)
+    }
 
-            match byte[0] {
-                b'=' => {
-                    is_key = false;
+    fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+        let mut pieces: Vec<&str> = Vec::new();
+        let mut in_quotes = false;
+        let mut start = 0;
+        for (index, c) in value.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                c if c == separator && !in_quotes => {
+                    pieces.push(&value[start..index]);
+                    start = index + c.len_utf8();
                 },
-                b'&' => {
-                    params.insert(HttpUrlDecoder::decode_utf_8(&key), HttpUrlDecoder::decode_utf_8(&value));
-                    key.clear();
-                    value.clear();
-                    is_key = true;
-                },
-                _ => {
-                    if is_key {
-                        key.push(byte[0] as char);
-                    }
-                    else {
-                        value.push(byte[0] as char);
-                    }
-                }
+                _ => (),
             }
         }
+        pieces.push(&value[start..]);
+        pieces
+    }
 
-        if !key.is_empty() && !value.is_empty() {
-            params.insert(HttpUrlDecoder::decode_utf_8(&key), HttpUrlDecoder::decode_utf_8(&value));
+    fn gen_params(raw: &[u8], parts: &[Part], body_type: HttpBodyType) -> Option<FormData> {
+        match body_type {
+            HttpBodyType::FormData if !parts.is_empty() => {
+                Some(Self::gen_params_form_data(parts))
+            },
+            HttpBodyType::UrlEncoded if !raw.is_empty() => {
+                Some(FormData::parse(raw))
+            },
+            _ => None,
         }
+    }
 
-        params
+    /// Text fields of the form, in the order sent
+    fn gen_params_form_data(parts: &[Part]) -> FormData {
+        let mut form = FormData::new();
+        for part in parts.iter().filter(|part| !part.is_file()) {
+            if let Ok(text) = part.text() {
+                form.append(&part.name, &text);
+            }
+        }
+        form
     }
 }
@@ -0,0 +1,106 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+type Chunk = Result<Vec<u8>, String>;
+
+/// Creates a streaming body, see HttpRequest::respond_stream
+///
+/// # Arguments
+///
+/// * `capacity` - Chunks buffered before the sender waits on the client to read
+pub fn channel(capacity: usize) -> (BodySender, BodyStream) {
+    let (sender, receiver) = mpsc::channel::<Chunk>(capacity.max(1));
+    (
+        BodySender {
+            sender,
+        },
+        BodyStream {
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+        },
+    )
+}
+
+/// Error returned once the client has gone away, so the producer can stop early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamClosed;
+
+impl std::fmt::Display for StreamClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Response stream closed by the connection")
+    }
+}
+
+impl std::error::Error for StreamClosed {}
+
+/// Producing half of a streaming body.
+///     The body ends once every sender is dropped, or fails with abort.
+#[derive(Clone)]
+pub struct BodySender {
+    sender: mpsc::Sender<Chunk>,
+}
+
+impl BodySender {
+    /// Sends a chunk, waiting while the connection is behind on writes
+    pub async fn send<B: Into<Vec<u8>>>(&self, chunk: B) -> Result<(), StreamClosed> {
+        self.sender.send(Ok(chunk.into())).await.map_err(|_| StreamClosed)
+    }
+
+    /// Sends a chunk from a thread outside of the runtime, blocking while the connection is behind on writes
+    ///     Panics if called from within an async context, use send there instead.
+    pub fn blocking_send<B: Into<Vec<u8>>>(&self, chunk: B) -> Result<(), StreamClosed> {
+        self.sender.blocking_send(Ok(chunk.into())).map_err(|_| StreamClosed)
+    }
+
+    /// Ends the body as failed, the connection is closed without completing the response
+    pub async fn abort(self, reason: &str) {
+        let _ = self.sender.send(Err(reason.to_string())).await;
+    }
+
+    /// Ends the body as failed from a thread outside of the runtime
+    pub fn blocking_abort(self, reason: &str) {
+        let _ = self.sender.blocking_send(Err(reason.to_string()));
+    }
+}
+
+impl std::io::Write for BodySender {
+    /// Blocks like blocking_send, so only write from threads outside of the runtime
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.blocking_send(buf) {
+            Ok(_) => Ok(buf.len()),
+            Err(closed) => Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, closed)),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BodySender {
+    fn drop(&mut self) {
+        // a panicking producer must not look like a body which finished
+        if std::thread::panicking() {
+            let _ = self.sender.try_send(Err("Body producer panicked".to_string()));
+        }
+    }
+}
+
+/// Consuming half of a streaming body, attached to an HttpResponse.
+///     Clones share the same stream, which can only be written once.
+#[derive(Clone)]
+pub struct BodyStream {
+    receiver: Arc<Mutex<Option<mpsc::Receiver<Chunk>>>>,
+}
+
+impl BodyStream {
+    pub(crate) fn take(&self) -> Option<mpsc::Receiver<Chunk>> {
+        match self.receiver.lock() {
+            Ok(mut receiver) => receiver.take(),
+            Err(error) => {
+                log::error!("Failed to get body stream lock!\n\t{}", error);
+                None
+            }
+        }
+    }
+}
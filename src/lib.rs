@@ -2,15 +2,28 @@
 #![allow(clippy::redundant_static_lifetimes)]
 pub mod server;
 pub mod router;
+pub mod middleware;
+pub mod handler;
+pub mod extract;
 pub mod http {
     pub mod request;
     pub mod response;
     pub mod shared;
     pub mod decoder;
+    pub mod connection;
+    pub mod chunked;
+    pub mod headers;
+    pub mod limits;
+    pub mod multipart;
+    pub mod status;
+    pub mod stream;
+    pub mod params;
+    pub mod form;
+    #[cfg(feature = "serde")]
+    pub mod json;
+    #[cfg(feature = "serde")]
+    pub mod deserializer;
 }
-#[macro_use]
-extern crate lazy_static;
-
 mod logger;
 
 #[derive(Clone)]
@@ -0,0 +1,58 @@
+use std::{future::Future, sync::Arc};
+
+use crate::{
+    handler::{BoxFuture, Endpoint},
+    http::{request::HttpRequest, response::HttpResponse},
+};
+
+/// Middleware run in order, shared by every request
+pub(crate) type MiddlewareChain = Arc<[Arc<dyn Middleware>]>;
+
+/// Code run around route handlers, e.g. auth, timing or header injection.
+///     Global middleware wraps every request, unmatched ones included, and runs before any route middleware.
+///     Async closures of the form `|http_request: HttpRequest, next: Next| async move { next.run(http_request).await }` are middleware too.
+pub trait Middleware: Send + Sync {
+    /// Handles the request, returning the response to write.
+    ///     Await `next.run` to continue to the rest of the pipeline and the handler,
+    ///     or return a response without calling it to short-circuit the pipeline.
+    fn handle<'a>(&'a self, http_req: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse>;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(HttpRequest, Next) -> Fut + Send + Sync,
+    Fut: Future<Output = HttpResponse> + Send + 'static,
+{
+    fn handle<'a>(&'a self, http_req: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(self(http_req, next))
+    }
+}
+
+/// Rest of the middleware pipeline, ending in the handler
+pub struct Next {
+    chain: MiddlewareChain,
+    index: usize,
+    endpoint: Endpoint,
+}
+
+impl Next {
+    pub(crate) fn new(chain: MiddlewareChain, endpoint: Endpoint) -> Next {
+        Next {
+            chain,
+            index: 0,
+            endpoint,
+        }
+    }
+
+    /// Runs the rest of the pipeline, returning the response of the handler.
+    ///     The response can be changed before it is returned, it is not written until the whole pipeline is done.
+    pub async fn run(mut self, http_req: HttpRequest) -> HttpResponse {
+        match self.chain.get(self.index).cloned() {
+            Some(middleware) => {
+                self.index += 1;
+                middleware.handle(http_req, self).await
+            },
+            None => (self.endpoint)(http_req).await,
+        }
+    }
+}
@@ -1,24 +1,45 @@
-use std::{
-    collections::HashMap,
-    net::TcpStream,
-};
+use std::{collections::HashMap, sync::Arc};
 
-use crate::http::{
-    request::{HttpRequest, HttpRequestMethod, HttpRequestFailure},
-    response::HttpResponse,
+use crate::{
+    handler::{Endpoint, Handler, RouteEndpoint, SyncHandler},
+    http::{
+        decoder::HttpUrlDecoder,
+        params::PathParams,
+        request::{HttpRequest, HttpRequestMethod},
+        response::{HttpResponse, IntoResponse},
+        status::StatusCode,
+    },
+    middleware::{Middleware, MiddlewareChain, Next},
 };
 
-use regex::Regex;
+use tree::{RouteMatch, RouteTree};
+
+mod tree;
+
+struct RouteHandler<S> {
+    handler: RouteEndpoint<S>,
+    middleware: MiddlewareChain,
+}
+
+type NotFoundHandler = Box<dyn Fn(&mut HttpRequest) + Send + Sync>;
+type MethodNotAllowedHandler = Box<dyn Fn(&mut HttpRequest, &[HttpRequestMethod]) + Send + Sync>;
 
-struct RouteHandler {
-    regex: Regex,
-    handler: Box<dyn Fn(&mut HttpRequest) + Send + Sync>,
+/// Routes requests to their handlers.
+///     S is the state shared by every handler, see HttpRouter::with_state.
+pub struct HttpRouter<S = ()> {
+    // the owning HttpServer wraps the router in an Arc once started
+    //  each connection task will get a clone of the Arc to share the routes
+    routes: HashMap<HttpRequestMethod, RouteTree<RouteHandler<S>>>,
+    state: Arc<S>,
+    middleware: MiddlewareChain,
+    not_found: Option<NotFoundHandler>,
+    method_not_allowed: Option<MethodNotAllowedHandler>,
 }
 
-pub struct HttpRouter {
-    // Arc is an Atomic wrapper to make the HashMap thread safe
-    //  each thread will get a clone of the wrapped data to achieve this
-    routes: HashMap<HttpRequestMethod, Vec<RouteHandler>>,
+impl Default for HttpRouter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HttpRouter {
@@ -26,91 +47,275 @@ impl HttpRouter {
         log::debug!("Router created! Added routes will be output to debug.");
         HttpRouter {
             routes: HashMap::new(),
+            state: Arc::new(()),
+            middleware: Arc::from(Vec::new()),
+            not_found: None,
+            method_not_allowed: None,
         }
     }
 
-    pub fn add_route<F>(&mut self, method: HttpRequestMethod, path: &str, handler: F)
+    /// Gives the router state shared by every handler, e.g. a DB pool or config.
+    ///     Handlers taking `&S` or `State<S>` can then be added, routes already added keep working without it.
+    pub fn with_state<S>(self, state: S) -> HttpRouter<S>
+    where
+        S: Send + Sync + 'static,
+    {
+        let unit: Arc<()> = Arc::new(());
+        let routes = self.routes.into_iter()
+            .map(|(method, tree)| {
+                let tree = tree.map(&mut |route_handler: RouteHandler<()>| {
+                    let handler = route_handler.handler;
+                    let unit = Arc::clone(&unit);
+                    RouteHandler {
+                        handler: Arc::new(move |http_req: HttpRequest, _: &Arc<S>| handler(http_req, &unit)) as RouteEndpoint<S>,
+                        middleware: route_handler.middleware,
+                    }
+                });
+                (method, tree)
+            })
+            .collect();
+        HttpRouter {
+            routes,
+            state: Arc::new(state),
+            middleware: self.middleware,
+            not_found: self.not_found,
+            method_not_allowed: self.method_not_allowed,
+        }
+    }
+}
+
+impl<S> HttpRouter<S>
+where
+    S: Send + Sync + 'static,
+{
+    /// State shared by every handler
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// Replaces the default 404 Not Found response for paths with no route.
+    ///     Like the 405 handler, it runs on the runtime's worker thread, so it must not block.
+    pub fn set_not_found_handler<F>(&mut self, handler: F)
     where
         F: Fn(&mut HttpRequest) + 'static + Send + Sync,
     {
-        let regex_pattern = self.convert_path_to_regex(path);
-        let regex = match Regex::new(&regex_pattern) {
-            Ok(reg) => reg,
-            Err(e) => {
-                log::error!("Path not added! Issue creating regex for {}!\n\t{}", path, e);
-                return;
-            }
-        };
+        self.not_found = Some(Box::new(handler));
+    }
+
+    /// Replaces the default 405 Method Not Allowed response for paths with routes, but none for the method.
+    ///     The handler receives the methods the path allows, an Allow header is added if it responds 405 without one.
+    pub fn set_method_not_allowed_handler<F>(&mut self, handler: F)
+    where
+        F: Fn(&mut HttpRequest, &[HttpRequestMethod]) + 'static + Send + Sync,
+    {
+        self.method_not_allowed = Some(Box::new(handler));
+    }
+
+    /// Adds middleware run around every request, in the order added
+    pub fn add_middleware<M>(&mut self, middleware: M)
+    where
+        M: Middleware + 'static,
+    {
+        let mut chain: Vec<Arc<dyn Middleware>> = self.middleware.to_vec();
+        chain.push(Arc::new(middleware));
+        self.middleware = Arc::from(chain);
+    }
+
+    /// Adds a route, the handler either responds itself or returns any IntoResponse, e.g. `Result<String, StatusCode>`
+    ///     Async handlers, and handlers taking the state or extractors, are added with add_handler.
+    ///     A path segment is either static, a parameter `{name}`, a parameter constrained by a regex `{name:[0-9]+}`,
+    ///     or a final wildcard `{*name}` capturing the rest of the path. Static segments are preferred over parameters, and parameters over wildcards.
+    ///
+    /// # Panics
+    ///
+    /// If the path is malformed or conflicts with a route of the method matching exactly the same paths,
+    ///     so a route can not silently go missing once the server starts.
+    pub fn add_route<F, R>(&mut self, method: HttpRequestMethod, path: &str, handler: F)
+    where
+        F: Fn(&mut HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.add_handler_with_middleware::<F, (SyncHandler, R)>(method, path, Vec::new(), handler);
+    }
+
+    /// Adds a route with middleware run around only its handler, after any global middleware
+    pub fn add_route_with_middleware<F, R>(&mut self, method: HttpRequestMethod, path: &str, middleware: Vec<Box<dyn Middleware>>, handler: F)
+    where
+        F: Fn(&mut HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.add_handler_with_middleware::<F, (SyncHandler, R)>(method, path, middleware, handler);
+    }
+
+    /// Adds a route for any kind of Handler, sync or async, with or without the state or extractors.
+    ///     Paths are as for add_route, and panic the same way if malformed or conflicting.
+    pub fn add_handler<H, Kind>(&mut self, method: HttpRequestMethod, path: &str, handler: H)
+    where
+        H: Handler<S, Kind>,
+    {
+        self.add_handler_with_middleware(method, path, Vec::new(), handler);
+    }
+
+    /// Adds a route for any kind of Handler, with middleware run around only its handler, after any global middleware
+    pub fn add_handler_with_middleware<H, Kind>(&mut self, method: HttpRequestMethod, path: &str, middleware: Vec<Box<dyn Middleware>>, handler: H)
+    where
+        H: Handler<S, Kind>,
+    {
         let route_handler = RouteHandler {
-            regex,
-            handler: Box::new(handler),
+            handler: handler.into_endpoint(),
+            middleware: middleware.into_iter().map(Arc::from).collect(),
         };
-        log::debug!(
-            "{} {} | Regex: {}",
-            method,
-            path,
-            route_handler.regex.to_string()
-        );
-        self.routes
-            .entry(method)
-            .or_insert(Vec::<RouteHandler>::new())
-            .push(route_handler);
-    }
-
-    pub fn handle_request(&self, stream: TcpStream) {
-        let start_time = std::time::Instant::now();
-        let h_req: Result<HttpRequest, HttpRequestFailure> = HttpRequest::new(stream);
-        let elapsed = start_time.elapsed();
-        log::debug!("Request parsing took {} microseconds", elapsed.as_micros());
-        match h_req {
-            Ok(mut http_req) => {
-                let req_ip: String = match &http_req.peer_addr {
-                    Some(addr) => addr.clone(),
-                    None => "IP DNE | Check Logs!".to_owned(),
-                };
-                log::info!("{} {} {}", req_ip, http_req.route.method, http_req.route.path);
-                let routes = &self.routes;
-
-                if let Some(handlers) = routes.get(&http_req.route.method) {
-                    for handler in handlers {
-                        if handler.regex.is_match(&http_req.route.path) {
-                            (handler.handler)(&mut http_req);
-                            if !http_req.responded() {
-                                log::debug!("Handler for {} {} did not respond! Responding with OK", http_req.route.method, http_req.route.path);
-                                http_req.respond(HttpResponse::ok());
-                            }
-                            return;
-                        }
+        match self.routes.entry(method.clone()).or_insert_with(RouteTree::new).insert(path, route_handler) {
+            Ok(_) => log::debug!("{} {}", method, path),
+            Err(e) => panic!("Route {} {} could not be added! {}", method, path, e),
+        }
+    }
+
+    /// Path of the route, as it was added, that a request for the method and path would be handled by.
+    ///     HEAD falls back to the GET route, None if the request would be answered 404 or 405.
+    pub fn matched_route(&self, method: &HttpRequestMethod, path: &str) -> Option<&str> {
+        self.find_route(method, path).map(|route_match| route_match.path)
+    }
+
+    /// Runs the handler matching the request, responding 404 for an unknown path or 405 for an unrouted method
+    ///     Returns the response to write, once every middleware and the handler are done.
+    pub async fn handle_request(self: Arc<Self>, http_req: HttpRequest) -> HttpResponse {
+        let req_ip: String = match &http_req.peer_addr {
+            Some(addr) => addr.clone(),
+            None => "IP DNE | Check Logs!".to_owned(),
+        };
+        log::info!("{} {} {}", req_ip, http_req.route.method, http_req.route.path);
+        let router = Arc::clone(&self);
+        let endpoint: Endpoint = Arc::new(move |http_req: HttpRequest| {
+            let router = Arc::clone(&router);
+            Box::pin(async move { router.dispatch(http_req).await })
+        });
+        Next::new(Arc::clone(&self.middleware), endpoint).run(http_req).await
+    }
+
+    /// Methods with a route matching the path, in a stable order.
+    ///     HEAD is included wherever GET is, and OPTIONS is always included as it is answered automatically.
+    ///     A path of `*` matches every route, as for `OPTIONS *`.
+    pub fn allowed_methods(&self, path: &str) -> Vec<HttpRequestMethod> {
+        let mut methods: Vec<HttpRequestMethod> = self.routes.iter()
+            .filter(|(_, tree)| path == "*" || tree.matches(path))
+            .map(|(method, _)| method.clone())
+            .collect();
+        if methods.is_empty() {
+            return methods;
+        }
+        if methods.contains(&HttpRequestMethod::Get) {
+            methods.push(HttpRequestMethod::Head);
+        }
+        methods.push(HttpRequestMethod::Options);
+        methods.sort_by_key(|method| (Self::method_order(method), method.to_string()));
+        methods.dedup();
+        methods
+    }
+
+    fn method_order(method: &HttpRequestMethod) -> u8 {
+        match method {
+            HttpRequestMethod::Get => 0,
+            HttpRequestMethod::Head => 1,
+            HttpRequestMethod::Post => 2,
+            HttpRequestMethod::Put => 3,
+            HttpRequestMethod::Patch => 4,
+            HttpRequestMethod::Delete => 5,
+            HttpRequestMethod::Options => 6,
+            HttpRequestMethod::Trace => 7,
+            HttpRequestMethod::Connect => 8,
+            HttpRequestMethod::Extension(_) => 9,
+        }
+    }
+
+    /// Value for an Allow header listing the methods of the path
+    pub fn allow_header(&self, path: &str) -> String {
+        Self::join_methods(&self.allowed_methods(path))
+    }
+
+    fn join_methods(methods: &[HttpRequestMethod]) -> String {
+        methods.iter()
+            .map(|method| method.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// Route of the method matching the path, HEAD is answered by the GET route when it has none of its own
+    fn find_route<'a, 'p>(&'a self, method: &HttpRequestMethod, path: &'p str) -> Option<RouteMatch<'a, 'p, RouteHandler<S>>> {
+        let found = self.routes.get(method).and_then(|tree| tree.find(path));
+        match found {
+            // the body of the GET response is dropped when written
+            None if *method == HttpRequestMethod::Head => self.routes.get(&HttpRequestMethod::Get)?.find(path),
+            found => found,
+        }
+    }
+
+    async fn dispatch(&self, mut http_req: HttpRequest) -> HttpResponse {
+        match self.resolve(&mut http_req) {
+            Some((handler, middleware)) => {
+                let state = Arc::clone(&self.state);
+                let endpoint: Endpoint = Arc::new(move |http_req: HttpRequest| handler(http_req, &state));
+                Next::new(middleware, endpoint).run(http_req).await
+            },
+            None => http_req.take_response().unwrap_or_else(HttpResponse::not_found),
+        }
+    }
+
+    /// Finds the route of the request and fills its path parameters.
+    ///     Returns None once the request is answered without a route, with 400, 404, 405 or OPTIONS.
+    fn resolve(&self, http_req: &mut HttpRequest) -> Option<(RouteEndpoint<S>, MiddlewareChain)> {
+        let method = http_req.route.method.clone();
+        let path = http_req.route.path.clone();
+        if let Some(route_match) = self.find_route(&method, &path) {
+            let mut path_params = PathParams::new();
+            for (name, value) in route_match.params {
+                match HttpUrlDecoder::decode_utf_8(value) {
+                    Ok(decoded) => path_params.insert(name, decoded),
+                    Err(e) => {
+                        log::debug!("Path parameter {} of {} could not be decoded: {}", name, path, e);
+                        http_req.respond(HttpResponse::bad_request().with_body(format!("{{\"error\": \"Invalid path parameter {}: {}\"}}", name, e)));
+                        return None;
                     }
-                    // respond with 404
-                    http_req.respond(HttpResponse::not_found());
-                } else {
-                    // respond with 404
-                    http_req.respond(HttpResponse::not_found());
                 }
-            },
-            Err(mut http_fail) => {
-                log::error!("Error occured from HttpRequest: \n\t{}", http_fail.fail_reason);
-                http_fail.respond(HttpResponse::bad_request());
             }
+            http_req.path_params = path_params;
+            return Some((Arc::clone(&route_match.value.handler), Arc::clone(&route_match.value.middleware)));
+        }
+
+        let allowed = self.allowed_methods(&path);
+        if allowed.is_empty() {
+            self.respond_not_found(http_req);
+        } else if method == HttpRequestMethod::Options {
+            http_req.respond(HttpResponse::new(StatusCode::NO_CONTENT).with_header("Allow", &Self::join_methods(&allowed)));
+        } else {
+            self.respond_method_not_allowed(http_req, &allowed);
         }
+        None
     }
 
-    fn convert_path_to_regex(&self, path: &str) -> String {
-        let mut regex_pattern = "^".to_string();
-        for segment in path.split('/') {
-            if !segment.is_empty() {
-                regex_pattern.push('/');
-            }
-            if segment.starts_with('{') && segment.ends_with('}') {
-                let param_name = &segment[1..segment.len() - 1];
-                regex_pattern.push_str(&format!("(?P<{}>[^/]+)", param_name));
-            } else {
-                regex_pattern.push_str(segment);
+    fn respond_not_found(&self, http_req: &mut HttpRequest) {
+        if let Some(not_found) = &self.not_found {
+            not_found(http_req);
+        }
+        if !http_req.responded() {
+            http_req.respond(HttpResponse::not_found());
+        }
+    }
+
+    /// Answers 405, which must list the allowed methods, per RFC 9110 15.5.6
+    fn respond_method_not_allowed(&self, http_req: &mut HttpRequest, allowed: &[HttpRequestMethod]) {
+        let allow = Self::join_methods(allowed);
+        if let Some(method_not_allowed) = &self.method_not_allowed {
+            method_not_allowed(http_req, allowed);
+        }
+        if !http_req.responded() {
+            http_req.respond(HttpResponse::new(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", &allow));
+        }
+        if let Some(http_res) = http_req.response_mut() {
+            if http_res.status == StatusCode::METHOD_NOT_ALLOWED && !http_res.headers.contains("Allow") {
+                http_res.headers.insert("Allow", &allow);
             }
         }
-        regex_pattern.push('$');
-        regex_pattern.clone()
     }
 }
 
@@ -125,3 +330,44 @@ impl std::fmt::Display for HttpRoute {
         write!(f, "Method: {} Path: {}", self.method, self.path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HttpRouter;
+    use crate::http::{request::{HttpRequest, HttpRequestMethod}, response::HttpResponse};
+
+    #[test]
+    fn add_route_infers_sync_closures() {
+        let mut router = HttpRouter::new();
+        router.add_route(HttpRequestMethod::Get, "/respond", |http_request| http_request.respond(HttpResponse::ok()));
+        router.add_route(HttpRequestMethod::Get, "/text", |_| "text");
+        router.add_route_with_middleware(HttpRequestMethod::Get, "/middleware", Vec::new(), |_| ());
+        router.add_handler(HttpRequestMethod::Get, "/async", |_: HttpRequest| async { "text" });
+        for path in ["/respond", "/text", "/middleware", "/async"] {
+            assert_eq!(router.matched_route(&HttpRequestMethod::Get, path), Some(path));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Route GET /person/{name} could not be added! Conflicts with /person/{id}")]
+    fn add_route_panics_on_conflict() {
+        let mut router = HttpRouter::new();
+        router.add_route(HttpRequestMethod::Get, "/person/{id}", |_| ());
+        router.add_route(HttpRequestMethod::Get, "/person/{name}", |_| ());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be the last segment")]
+    fn add_handler_panics_on_malformed_path() {
+        let mut router = HttpRouter::new();
+        router.add_handler(HttpRequestMethod::Get, "/files/{*rest}/more", |_: HttpRequest| async {});
+    }
+
+    #[test]
+    fn same_path_may_be_added_for_other_methods() {
+        let mut router = HttpRouter::new();
+        router.add_route(HttpRequestMethod::Get, "/person/{id}", |_| ());
+        router.add_route(HttpRequestMethod::Post, "/person/{name}", |_| ());
+        assert_eq!(router.matched_route(&HttpRequestMethod::Post, "/person/1"), Some("/person/{name}"));
+    }
+}
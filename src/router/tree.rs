@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+/// Routes of one method, as a prefix tree of path segments.
+///     A lookup walks one segment at a time, so it costs the depth of the path rather than the number of routes.
+///     Each segment prefers a static child, then a parameter, then a wildcard, backtracking if a branch ends without a route.
+pub(crate) struct RouteTree<T> {
+    root: Node<T>,
+}
+
+/// Route found for a path, with the raw (still percent-encoded) values of its parameters in route order
+pub(crate) struct RouteMatch<'a, 'p, T> {
+    pub value: &'a T,
+    pub path: &'a str,
+    pub params: Vec<(&'a str, &'p str)>,
+}
+
+struct Route<T> {
+    /// Path as it was added, e.g. `/person/{person_id:[0-9]+}`
+    path: String,
+    param_names: Vec<String>,
+    value: T,
+}
+
+struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    /// Constrained parameters in the order added, then at most one unconstrained parameter
+    params: Vec<ParamNode<T>>,
+    /// Route matching the rest of the path, a wildcard is always last
+    wildcard: Option<Box<Route<T>>>,
+    route: Option<Route<T>>,
+}
+
+struct ParamNode<T> {
+    /// Constraint as written in the path and compiled to match the whole segment
+    constraint: Option<(String, Regex)>,
+    node: Node<T>,
+}
+
+enum Segment<'a> {
+    Static(&'a str),
+    Param { name: &'a str, constraint: Option<&'a str> },
+    Wildcard(&'a str),
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            statics: HashMap::new(),
+            params: Vec::new(),
+            wildcard: None,
+            route: None,
+        }
+    }
+}
+
+impl<T> RouteTree<T> {
+    pub fn new() -> RouteTree<T> {
+        RouteTree {
+            root: Node::default(),
+        }
+    }
+
+    /// Adds the route, failing if the path is malformed or another route already matches exactly the same paths.
+    ///     Empty segments are ignored, so `/person/` is the same route as `/person`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Segments are static, `{name}`, `{name:regex}` matching the whole segment, or a final `{*name}` matching the rest of the path
+    pub fn insert(&mut self, path: &str, value: T) -> Result<(), String> {
+        let segments = Self::parse(path)?;
+        let mut param_names: Vec<String> = Vec::new();
+        let mut node = &mut self.root;
+        for segment in segments {
+            node = match segment {
+                Segment::Static(segment) => node.statics.entry(segment.to_string()).or_default(),
+                Segment::Param { name, constraint } => {
+                    param_names.push(name.to_string());
+                    Self::param_child(node, constraint)?
+                },
+                Segment::Wildcard(name) => {
+                    param_names.push(name.to_string());
+                    return match &node.wildcard {
+                        Some(existing) => Err(format!("Conflicts with {}", existing.path)),
+                        None => {
+                            node.wildcard = Some(Box::new(Route { path: path.to_string(), param_names, value }));
+                            Ok(())
+                        }
+                    };
+                },
+            };
+        }
+        match &node.route {
+            Some(existing) => Err(format!("Conflicts with {}", existing.path)),
+            None => {
+                node.route = Some(Route { path: path.to_string(), param_names, value });
+                Ok(())
+            }
+        }
+    }
+
+    /// Route matching the request path, None if no route does or the path does not start with `/`
+    pub fn find<'a, 'p>(&'a self, path: &'p str) -> Option<RouteMatch<'a, 'p, T>> {
+        let remaining = match path {
+            "/" => None,
+            path => Some(path.strip_prefix('/')?),
+        };
+        let mut values: Vec<&'p str> = Vec::new();
+        let route = self.root.find(remaining, &mut values)?;
+        Some(RouteMatch {
+            value: &route.value,
+            path: &route.path,
+            params: route.param_names.iter().map(String::as_str).zip(values).collect(),
+        })
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    /// Converts every route's value, keeping the shape of the tree
+    pub fn map<U, F>(self, f: &mut F) -> RouteTree<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        RouteTree {
+            root: self.root.map(f),
+        }
+    }
+
+    /// Child for a parameter with the constraint, constrained parameters are tried before an unconstrained one
+    fn param_child<'n>(node: &'n mut Node<T>, constraint: Option<&str>) -> Result<&'n mut Node<T>, String> {
+        let existing = node.params.iter()
+            .position(|param| param.constraint.as_ref().map(|(source, _)| source.as_str()) == constraint);
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                let constraint = match constraint {
+                    Some(constraint) => match Regex::new(&format!("^(?:{})$", constraint)) {
+                        Ok(regex) => Some((constraint.to_string(), regex)),
+                        Err(e) => return Err(format!("Invalid constraint {}\n\t{}", constraint, e)),
+                    },
+                    None => None,
+                };
+                let index = match constraint {
+                    Some(_) => node.params.iter().take_while(|param| param.constraint.is_some()).count(),
+                    None => node.params.len(),
+                };
+                node.params.insert(index, ParamNode { constraint, node: Node::default() });
+                index
+            }
+        };
+        Ok(&mut node.params[index].node)
+    }
+
+    fn parse(path: &str) -> Result<Vec<Segment<'_>>, String> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut names: Vec<&str> = Vec::new();
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            if let Some(Segment::Wildcard(name)) = segments.last() {
+                return Err(format!("Wildcard {{*{}}} must be the last segment", name));
+            }
+            let parsed = match segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')) {
+                Some(inner) => match inner.strip_prefix('*') {
+                    Some(name) => Segment::Wildcard(name),
+                    None => match inner.split_once(':') {
+                        Some((name, constraint)) => Segment::Param { name, constraint: Some(constraint) },
+                        None => Segment::Param { name: inner, constraint: None },
+                    },
+                },
+                None => Segment::Static(segment),
+            };
+            if let Segment::Param { name, .. } | Segment::Wildcard(name) = parsed {
+                if name.is_empty() {
+                    return Err(format!("Parameter {} has no name", segment));
+                }
+                if names.contains(&name) {
+                    return Err(format!("Parameter {} is used more than once", name));
+                }
+                names.push(name);
+            }
+            segments.push(parsed);
+        }
+        Ok(segments)
+    }
+}
+
+impl<T> Node<T> {
+    /// Route matching the rest of the path below this node
+    ///
+    /// # Arguments
+    ///
+    /// * `remaining` - Segments left to match without the leading `/`, None if there are none
+    /// * `values` - Raw values of the parameters matched on the way to this node
+    fn find<'a, 'p>(&'a self, remaining: Option<&'p str>, values: &mut Vec<&'p str>) -> Option<&'a Route<T>> {
+        let path = match remaining {
+            Some(path) => path,
+            None => return self.route.as_ref(),
+        };
+        let (segment, rest) = match path.split_once('/') {
+            Some((segment, rest)) => (segment, Some(rest)),
+            None => (path, None),
+        };
+
+        if let Some(route) = self.statics.get(segment).and_then(|child| child.find(rest, values)) {
+            return Some(route);
+        }
+        if !segment.is_empty() {
+            for param in &self.params {
+                if param.constraint.as_ref().is_some_and(|(_, regex)| !regex.is_match(segment)) {
+                    continue;
+                }
+                values.push(segment);
+                if let Some(route) = param.node.find(rest, values) {
+                    return Some(route);
+                }
+                values.pop();
+            }
+        }
+        match &self.wildcard {
+            Some(route) if !path.is_empty() => {
+                values.push(path);
+                Some(route)
+            },
+            _ => None,
+        }
+    }
+
+    fn map<U, F>(self, f: &mut F) -> Node<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        Node {
+            statics: self.statics.into_iter()
+                .map(|(segment, child)| (segment, child.map(f)))
+                .collect(),
+            params: self.params.into_iter()
+                .map(|param| ParamNode { constraint: param.constraint, node: param.node.map(f) })
+                .collect(),
+            wildcard: self.wildcard.map(|route| Box::new(route.map(f))),
+            route: self.route.map(|route| route.map(f)),
+        }
+    }
+}
+
+impl<T> Route<T> {
+    fn map<U, F>(self, f: &mut F) -> Route<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        Route {
+            path: self.path,
+            param_names: self.param_names,
+            value: f(self.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RouteTree;
+
+    fn tree(paths: &[&'static str]) -> RouteTree<&'static str> {
+        let mut tree = RouteTree::new();
+        for path in paths {
+            if let Err(e) = tree.insert(path, *path) {
+                panic!("{}: {}", path, e);
+            }
+        }
+        tree
+    }
+
+    /// Route matching the path, with its parameters
+    fn find<'p>(tree: &RouteTree<&'static str>, path: &'p str) -> Option<(&'static str, Vec<(String, &'p str)>)> {
+        tree.find(path).map(|found| {
+            let params = found.params.into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+            (*found.value, params)
+        })
+    }
+
+    fn params(pairs: &[(&str, &'static str)]) -> Vec<(String, &'static str)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), *value)).collect()
+    }
+
+    #[test]
+    fn matches_static_routes() {
+        let tree = tree(&["/", "/person", "/person/list"]);
+        assert_eq!(find(&tree, "/"), Some(("/", params(&[]))));
+        assert_eq!(find(&tree, "/person"), Some(("/person", params(&[]))));
+        assert_eq!(find(&tree, "/person/list"), Some(("/person/list", params(&[]))));
+        assert!(find(&tree, "/person/lis").is_none());
+        assert!(find(&tree, "/people").is_none());
+        assert!(find(&tree, "").is_none());
+        assert!(find(&tree, "person").is_none());
+    }
+
+    #[test]
+    fn trailing_slash_is_a_different_path() {
+        let tree = tree(&["/person"]);
+        assert!(find(&tree, "/person/").is_none());
+        assert!(find(&tree, "//person").is_none());
+        // empty segments are ignored when a route is added
+        assert!(RouteTree::<()>::new().insert("/person/", ()).is_ok());
+    }
+
+    #[test]
+    fn prefers_static_then_param_then_wildcard() {
+        // added in reverse priority, the order must not matter
+        let tree = tree(&["/files/{*rest}", "/files/{name}", "/files/readme"]);
+        assert_eq!(find(&tree, "/files/readme"), Some(("/files/readme", params(&[]))));
+        assert_eq!(find(&tree, "/files/other"), Some(("/files/{name}", params(&[("name", "other")]))));
+        assert_eq!(find(&tree, "/files/a/b"), Some(("/files/{*rest}", params(&[("rest", "a/b")]))));
+    }
+
+    #[test]
+    fn prefers_constrained_params() {
+        let tree = tree(&["/person/{name}", "/person/{id:[0-9]+}"]);
+        assert_eq!(find(&tree, "/person/42"), Some(("/person/{id:[0-9]+}", params(&[("id", "42")]))));
+        assert_eq!(find(&tree, "/person/bob"), Some(("/person/{name}", params(&[("name", "bob")]))));
+    }
+
+    #[test]
+    fn constraints_match_the_whole_segment() {
+        let tree = tree(&["/person/{id:[0-9]+}", "/code/{code:[a-z]{2}|[0-9]{3}}"]);
+        assert!(find(&tree, "/person/42").is_some());
+        assert!(find(&tree, "/person/42a").is_none());
+        assert!(find(&tree, "/person/a42").is_none());
+        assert!(find(&tree, "/code/ab").is_some());
+        assert!(find(&tree, "/code/123").is_some());
+        assert!(find(&tree, "/code/ab1").is_none());
+        assert!(find(&tree, "/code/1234").is_none());
+    }
+
+    #[test]
+    fn backtracks_when_a_branch_has_no_route() {
+        let tree = tree(&["/users/new/edit", "/users/{id}/posts", "/users/{id:[0-9]+}/avatar", "/users/{*rest}"]);
+        // the static branch has no route for posts, so the param is tried
+        assert_eq!(find(&tree, "/users/new/posts"), Some(("/users/{id}/posts", params(&[("id", "new")]))));
+        // the constrained param has no route for posts, so the unconstrained one is tried
+        assert_eq!(find(&tree, "/users/7/posts"), Some(("/users/{id}/posts", params(&[("id", "7")]))));
+        assert_eq!(find(&tree, "/users/7/avatar"), Some(("/users/{id:[0-9]+}/avatar", params(&[("id", "7")]))));
+        // nothing below matches, so the wildcard takes the rest, without params from abandoned branches
+        assert_eq!(find(&tree, "/users/7/likes"), Some(("/users/{*rest}", params(&[("rest", "7/likes")]))));
+    }
+
+    #[test]
+    fn matches_params_in_route_order() {
+        let tree = tree(&["/person/{person_id}/pet/{pet_id}"]);
+        assert_eq!(
+            find(&tree, "/person/3/pet/%20x"),
+            Some(("/person/{person_id}/pet/{pet_id}", params(&[("person_id", "3"), ("pet_id", "%20x")]))),
+        );
+        assert!(find(&tree, "/person//pet/1").is_none());
+    }
+
+    #[test]
+    fn wildcard_needs_a_remainder() {
+        let tree = tree(&["/static/{*file}"]);
+        assert_eq!(find(&tree, "/static/css/site.css"), Some(("/static/{*file}", params(&[("file", "css/site.css")]))));
+        assert!(find(&tree, "/static").is_none());
+        assert!(find(&tree, "/static/").is_none());
+    }
+
+    #[test]
+    fn rejects_conflicting_routes() {
+        let mut tree = tree(&["/person/{person_id:[0-9]+}", "/person/{name}", "/static/{*file}", "/about"]);
+        assert_eq!(tree.insert("/person/{id:[0-9]+}", "dup").err(), Some("Conflicts with /person/{person_id:[0-9]+}".to_string()));
+        assert_eq!(tree.insert("/person/{other}", "dup").err(), Some("Conflicts with /person/{name}".to_string()));
+        assert_eq!(tree.insert("/static/{*path}", "dup").err(), Some("Conflicts with /static/{*file}".to_string()));
+        assert_eq!(tree.insert("/about/", "dup").err(), Some("Conflicts with /about".to_string()));
+        // a different constraint matches different paths
+        assert!(tree.insert("/person/{code:[a-z]+}", "other").is_ok());
+        // the first route is kept
+        assert_eq!(find(&tree, "/person/1"), Some(("/person/{person_id:[0-9]+}", params(&[("person_id", "1")]))));
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        let mut tree: RouteTree<()> = RouteTree::new();
+        assert!(tree.insert("/files/{*rest}/more", ()).is_err());
+        assert!(tree.insert("/person/{}", ()).is_err());
+        assert!(tree.insert("/person/{:[0-9]+}", ()).is_err());
+        assert!(tree.insert("/files/{*}", ()).is_err());
+        assert!(tree.insert("/person/{id}/pet/{id}", ()).is_err());
+        assert!(tree.insert("/person/{id:[0-9}", ()).is_err());
+        assert!(!tree.matches("/files/a/more"));
+    }
+}
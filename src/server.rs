@@ -1,18 +1,55 @@
-use std::{net::TcpListener, sync::{Arc, RwLock}};
+use std::{net::TcpListener, sync::Arc, time::Duration};
 
-use tokio::runtime::Builder;
+use tokio::{runtime::Builder, sync::watch, task::JoinSet};
 
-use crate::{router::HttpRouter, http::request::{HttpRequest, HttpRequestMethod}, logger};
+use crate::{
+    router::HttpRouter,
+    middleware::Middleware,
+    handler::Handler,
+    http::{
+        connection::{ConnectionConfig, HttpConnection},
+        limits::{ServerLimits, ServerTimeouts},
+        request::{HttpRequest, HttpRequestMethod},
+        response::IntoResponse,
+    },
+    logger,
+};
 
-lazy_static! {
-    static ref ROUTER: Arc<RwLock<HttpRouter>> = Arc::new(RwLock::new(HttpRouter::new()));
+/// Stops a running HttpServer from any thread.
+///     Cloned handles all signal the same server.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
 }
 
-pub struct HttpServer {
+impl ShutdownHandle {
+    /// Stops accepting connections and begins draining in-flight requests.
+    ///     Calling this before the server starts makes start return immediately.
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    fn subscribe(&self) -> watch::Receiver<bool> {
+        self.sender.subscribe()
+    }
+}
+
+/// HTTP/1.1 server owning its listener and router.
+///     S is the state shared by every handler, see HttpServer::with_state.
+pub struct HttpServer<S = ()> {
     #[allow(dead_code)]
     bind_addr: String,
     tcp_listener: TcpListener,
     pool_size: usize,
+    router: HttpRouter<S>,
+    shutdown_handle: ShutdownHandle,
+    shutdown_timeout: Duration,
+    shutdown_on_signal: bool,
+    connection_config: ConnectionConfig,
 }
 
 impl HttpServer {
@@ -29,28 +66,166 @@ impl HttpServer {
             bind_addr: bind_addr.to_string(),
             tcp_listener,
             pool_size: 12,
+            router: HttpRouter::new(),
+            shutdown_handle: ShutdownHandle {
+                sender: Arc::new(watch::channel(false).0),
+            },
+            shutdown_timeout: Duration::from_secs(30),
+            shutdown_on_signal: false,
+            connection_config: ConnectionConfig::default(),
         }
     }
 
-    pub fn add_route(&mut self, method: HttpRequestMethod, path: &str, handler: fn(&mut HttpRequest)) {
-        match ROUTER.write() {
-            Ok(mut router) => {
-                router.add_route(method, path, handler);
-            },
-            Err(error) => {
-                log::error!("Failed to get router lock! Route not added!\n\t{}", error.to_string());
-            }
-        };
+    /// Gives the server state shared by every handler, e.g. a DB pool or config.
+    ///     Handlers may then take it as `&S` when sync or `State<S>` when async,
+    ///     a handler taking any other type fails to compile when its route is added.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - Shared by every request, wrap any field that changes in a Mutex or atomic
+    pub fn with_state<S>(self, state: S) -> HttpServer<S>
+    where
+        S: Send + Sync + 'static,
+    {
+        HttpServer {
+            bind_addr: self.bind_addr,
+            tcp_listener: self.tcp_listener,
+            pool_size: self.pool_size,
+            router: self.router.with_state(state),
+            shutdown_handle: self.shutdown_handle,
+            shutdown_timeout: self.shutdown_timeout,
+            shutdown_on_signal: self.shutdown_on_signal,
+            connection_config: self.connection_config,
+        }
+    }
+}
+
+impl<S> HttpServer<S>
+where
+    S: Send + Sync + 'static,
+{
+
+    /// Adds a route, see HttpRouter::add_route
+    ///     Panics if the path is malformed or conflicts with another route of the method.
+    pub fn add_route<F, R>(&mut self, method: HttpRequestMethod, path: &str, handler: F)
+    where
+        F: Fn(&mut HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_route(method, path, handler);
     }
 
-    pub fn set_pool_size(mut self, pool_size: usize) -> HttpServer {
+    /// Adds a route for any kind of handler, e.g. async or taking extractors, see HttpRouter::add_handler
+    pub fn add_handler<H, Kind>(&mut self, method: HttpRequestMethod, path: &str, handler: H)
+    where
+        H: Handler<S, Kind>,
+    {
+        self.router.add_handler(method, path, handler);
+    }
+
+    /// Adds middleware run around every request, see HttpRouter::add_middleware
+    pub fn add_middleware<M>(&mut self, middleware: M)
+    where
+        M: Middleware + 'static,
+    {
+        self.router.add_middleware(middleware);
+    }
+
+    /// Adds a route with middleware run around only its handler, see HttpRouter::add_route_with_middleware
+    pub fn add_route_with_middleware<F, R>(&mut self, method: HttpRequestMethod, path: &str, middleware: Vec<Box<dyn Middleware>>, handler: F)
+    where
+        F: Fn(&mut HttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router.add_route_with_middleware(method, path, middleware, handler);
+    }
+
+    /// Adds a route for any kind of handler with middleware run around only its handler, see HttpRouter::add_handler_with_middleware
+    pub fn add_handler_with_middleware<H, Kind>(&mut self, method: HttpRequestMethod, path: &str, middleware: Vec<Box<dyn Middleware>>, handler: H)
+    where
+        H: Handler<S, Kind>,
+    {
+        self.router.add_handler_with_middleware(method, path, middleware, handler);
+    }
+
+    /// Replaces the default 404 Not Found response, see HttpRouter::set_not_found_handler
+    pub fn set_not_found_handler<F>(&mut self, handler: F)
+    where
+        F: Fn(&mut HttpRequest) + 'static + Send + Sync,
+    {
+        self.router.set_not_found_handler(handler);
+    }
+
+    /// Replaces the default 405 Method Not Allowed response, see HttpRouter::set_method_not_allowed_handler
+    pub fn set_method_not_allowed_handler<F>(&mut self, handler: F)
+    where
+        F: Fn(&mut HttpRequest, &[HttpRequestMethod]) + 'static + Send + Sync,
+    {
+        self.router.set_method_not_allowed_handler(handler);
+    }
+
+    /// Replaces the routes of this server with a pre-built router.
+    /// Any routes previously added to the server are dropped, and the state is the router's.
+    ///
+    /// # Arguments
+    ///
+    /// * `router` - HttpRouter which will handle every request for this server
+    pub fn set_router(mut self, router: HttpRouter<S>) -> HttpServer<S> {
+        self.router = router;
+        self
+    }
+
+    pub fn set_pool_size(mut self, pool_size: usize) -> HttpServer<S> {
         self.pool_size = pool_size;
         self
     }
 
+    /// Max time in-flight requests are given to finish once shutdown begins (default is 30 seconds).
+    ///     Connections still open after the deadline are dropped.
+    pub fn set_shutdown_timeout(mut self, shutdown_timeout: Duration) -> HttpServer<S> {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Shuts the server down gracefully on SIGINT or SIGTERM (default is false).
+    pub fn set_shutdown_on_signal(mut self, shutdown_on_signal: bool) -> HttpServer<S> {
+        self.shutdown_on_signal = shutdown_on_signal;
+        self
+    }
+
+    /// Max time an idle keep-alive connection is held open waiting for the next request (default is 5 seconds).
+    pub fn set_keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> HttpServer<S> {
+        self.connection_config.keep_alive_timeout = keep_alive_timeout;
+        self
+    }
+
+    /// Max requests served on one connection before it is closed (default is 100).
+    pub fn set_max_requests_per_connection(mut self, max_requests: usize) -> HttpServer<S> {
+        self.connection_config.max_requests = max_requests;
+        self
+    }
+
+    /// Caps on request line, header, body and form sizes, see ServerLimits for the defaults.
+    pub fn set_limits(mut self, limits: ServerLimits) -> HttpServer<S> {
+        self.connection_config.limits = limits;
+        self
+    }
+
+    /// Deadlines for reading requests, running handlers and writing responses, see ServerTimeouts for the defaults.
+    pub fn set_timeouts(mut self, timeouts: ServerTimeouts) -> HttpServer<S> {
+        self.connection_config.timeouts = timeouts;
+        self
+    }
+
+    /// Handle which may be moved to another thread to stop the server once started.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+
     /// Begins handling incoming connections.
-    ///
-    pub fn start(&self) {
+    ///     The server owns its router, so no routes may be added once started.
+    ///     Blocks until the server is shut down and in-flight requests are drained.
+    pub fn start(self) {
         let runtime = Builder::new_multi_thread()
             .worker_threads(self.pool_size)
             .enable_all()
@@ -58,34 +233,104 @@ impl HttpServer {
             .unwrap();
 
 
-        for stream_res in self.tcp_listener.incoming() {
-            match stream_res {
-                Ok(stream) => {
-                    runtime.spawn(async move {
-                        match ROUTER.read() {
-                            Ok(ok_router) => {
-                                ok_router.handle_request(stream);
-                            },
-                            Err(error) => {
-                                log::error!("Failed to get router lock!\n\t{}", error.to_string());
-                                return;
-                            }
-                        };
-                    });
-                },
-                Err(error) => match error.kind() {
-                    std::io::ErrorKind::WouldBlock => {
-                        log::warn!("Waiting for network socket to be ready");
-                        continue;
+        let router: Arc<HttpRouter<S>> = Arc::new(self.router);
+        let tcp_listener = self.tcp_listener;
+        let shutdown_handle = self.shutdown_handle;
+        let shutdown_timeout = self.shutdown_timeout;
+        let shutdown_on_signal = self.shutdown_on_signal;
+        let connection_config: Arc<ConnectionConfig> = Arc::new(self.connection_config);
+
+        runtime.block_on(async move {
+            let tcp_listener = match Self::into_async_listener(tcp_listener) {
+                Ok(lis) => lis,
+                Err(error) => {
+                    log::error!("Failed to hand the listener to the runtime!\n\t{}", error);
+                    return;
+                }
+            };
+
+            if shutdown_on_signal {
+                let signal_handle = shutdown_handle.clone();
+                tokio::spawn(async move {
+                    Self::wait_for_signal().await;
+                    signal_handle.shutdown();
+                });
+            }
+
+            let mut shutdown_rx = shutdown_handle.subscribe();
+            let mut connections: JoinSet<()> = JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = tcp_listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            let connection = HttpConnection::new(
+                                stream,
+                                Arc::clone(&router),
+                                Arc::clone(&connection_config),
+                                shutdown_handle.subscribe(),
+                            );
+                            connections.spawn(connection.serve());
+                        },
+                        Err(error) => {
+                            let err: String = error.to_string();
+                            log::error!("{}", err);
+                        }
                     },
-                    _ => {
-                        let err: String = error.to_string();
-                        log::error!("{}", err);
-                    }
+                    // reap finished connections so the set does not grow forever
+                    Some(_) = connections.join_next(), if !connections.is_empty() => (),
+                    _ = shutdown_rx.wait_for(|shutdown| *shutdown) => break,
                 }
             }
+
+            drop(tcp_listener);
+            log::info!("Shutting down! Waiting up to {} seconds for {} connection(s) to finish.", shutdown_timeout.as_secs(), connections.len());
+            let drain = async {
+                while connections.join_next().await.is_some() {}
+            };
+            if tokio::time::timeout(shutdown_timeout, drain).await.is_err() {
+                log::warn!("Shutdown deadline passed! Dropping {} connection(s).", connections.len());
+                connections.abort_all();
+            }
+        });
+        runtime.shutdown_background();
+        log::info!("Server stopped.");
+    }
+
+    /// Resolves once SIGINT (Ctrl+C) or, on unix, SIGTERM is received.
+    async fn wait_for_signal() {
+        let interrupt = async {
+            if let Err(error) = tokio::signal::ctrl_c().await {
+                log::error!("Failed to listen for SIGINT!\n\t{}", error);
+                std::future::pending::<()>().await;
+            }
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(mut signal) => {
+                    signal.recv().await;
+                },
+                Err(error) => {
+                    log::error!("Failed to listen for SIGTERM!\n\t{}", error);
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = interrupt => log::info!("Received SIGINT."),
+            _ = terminate => log::info!("Received SIGTERM."),
         }
-        runtime.shutdown_timeout(std::time::Duration::from_secs(30));
+    }
+
+    /// Converts the bound std listener into a tokio listener.
+    ///     Must be called from within the runtime.
+    fn into_async_listener(tcp_listener: TcpListener) -> std::io::Result<tokio::net::TcpListener> {
+        tcp_listener.set_nonblocking(true)?;
+        tokio::net::TcpListener::from_std(tcp_listener)
     }
 
     fn start_listening(bind_addr: &str) -> TcpListener {